@@ -26,8 +26,9 @@ if [ "$1" = "--clean" ]; then
     cargo clean
 fi
 
-# Build in release mode with optimizations
-cargo build --release
+# Build in release mode with optimizations, with PostgreSQL support so a
+# configured DATABASE_URL keeps working
+cargo build --release --features postgres,tls
 
 if [ $? -eq 0 ]; then
     echo -e "${GREEN}✓ Build successful!${NC}"
@@ -65,7 +65,7 @@ fi
 # Check if binary exists
 if [ ! -f "target/release/clog" ]; then
     echo -e "${YELLOW}Binary not found. Building clog...${NC}"
-    cargo build --release
+    cargo build --release --features postgres,tls
     if [ $? -ne 0 ]; then
         echo -e "${RED}✗ Build failed${NC}"
         exit 1
@@ -27,6 +27,35 @@ CREATE TABLE IF NOT EXISTS log_entries (
     CONSTRAINT unique_event_per_device UNIQUE (event_id, device_id)
 );
 
+-- Working tree state at log time (added after the initial release)
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_staged INTEGER;
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_unstaged INTEGER;
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_untracked INTEGER;
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_upstream TEXT;
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_ahead INTEGER;
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_behind INTEGER;
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_describe TEXT;
+-- Stable repository identity (normalized origin URL or root commit)
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_id TEXT;
+-- Linked worktree the entry was logged in (repo_root is the main checkout),
+-- and the superproject when logged inside a submodule
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_worktree TEXT;
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_superproject TEXT;
+
+-- Event entries written by git hooks ('commit', 'checkout', 'merge', 'rewrite'); NULL for notes
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS kind TEXT;
+
+-- Commit an entry was logged at before a rebase or amend rewrote it (repo_commit is the rewritten one)
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_commit_original TEXT;
+-- Version control system the repo_* columns came from ('git', 'hg', 'jj'); NULL on older entries means git
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS vcs TEXT;
+-- Project root from markers (.clog-project, Cargo.toml, package.json, pyproject.toml)
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS project_root TEXT;
+-- Working-tree diff stored with --snapshot: SHA-256 of the patch under ~/.clog/blobs
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS snapshot TEXT;
+-- jj change id of the working-copy commit @ (repo_commit is the git commit @ sits on)
+ALTER TABLE log_entries ADD COLUMN IF NOT EXISTS repo_change TEXT;
+
 -- Indexes for efficient querying
 CREATE INDEX IF NOT EXISTS idx_log_entries_device_timestamp 
     ON log_entries(device_id, timestamp DESC);
@@ -40,9 +69,26 @@ CREATE INDEX IF NOT EXISTS idx_log_entries_session
 CREATE INDEX IF NOT EXISTS idx_log_entries_repo 
     ON log_entries(repo_root, timestamp DESC);
 
+CREATE INDEX IF NOT EXISTS idx_log_entries_repo_id 
+    ON log_entries(repo_id, timestamp DESC);
+
+CREATE INDEX IF NOT EXISTS idx_log_entries_project
+    ON log_entries(project_root, timestamp DESC);
+
 CREATE INDEX IF NOT EXISTS idx_log_entries_name 
     ON log_entries(name, timestamp DESC);
 
+-- Paths modified or staged in the working tree when an entry was logged
+-- (only captured with --files or the `log.files` setting)
+CREATE TABLE IF NOT EXISTS entry_files (
+    event_id TEXT NOT NULL REFERENCES log_entries(event_id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    PRIMARY KEY (event_id, path)
+);
+
+CREATE INDEX IF NOT EXISTS idx_entry_files_path
+    ON entry_files(path);
+
 -- Create sync_state table to track what each device has synced
 CREATE TABLE IF NOT EXISTS sync_state (
     device_id TEXT NOT NULL REFERENCES devices(device_id),
@@ -58,12 +104,15 @@ BEGIN
     INSERT INTO devices (device_id)
     VALUES (NEW.device_id)
     ON CONFLICT (device_id) 
-    DO UPDATE SET last_seen = CURRENT_TIMESTAMP;
+    DO UPDATE SET last_seen = CURRENT_TIMESTAMP
+    -- Once per transaction: a batch insert would otherwise rewrite the row for every entry
+    WHERE devices.last_seen IS DISTINCT FROM CURRENT_TIMESTAMP;
     RETURN NEW;
 END;
 $$ LANGUAGE plpgsql;
 
 -- Trigger to auto-register devices on first log entry
+DROP TRIGGER IF EXISTS register_device_on_insert ON log_entries;
 CREATE TRIGGER register_device_on_insert
     BEFORE INSERT ON log_entries
     FOR EACH ROW
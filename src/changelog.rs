@@ -0,0 +1,229 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use chrono::{Local, NaiveDate};
+use clap::ValueEnum;
+use crate::models::{Commit, LogEntry};
+
+/// How changelog entries are grouped into sections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    /// One section per commit, newest first
+    Commit,
+    /// One section per session name
+    Session,
+    /// One section per local day, newest first
+    Day,
+}
+
+/// Markdown used when no `--template` is given
+const DEFAULT_TEMPLATE: &str = "\
+# Changelog {range}
+--- group
+
+## {title}
+
+--- entry
+- {message} _({name}, {date} {time})_
+";
+
+/// A changelog template: a header, a block repeated per group and a block
+/// repeated per entry. Sections are introduced by `--- group` and `--- entry`
+/// lines; `{placeholder}`s are substituted, unknown ones are left as is.
+pub struct Template {
+    header: String,
+    group: String,
+    entry: String,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, Box<dyn std::error::Error>> {
+        let mut sections: [String; 3] = Default::default();
+        let mut current = 0;
+        for line in source.split_inclusive('\n') {
+            match line.trim_end() {
+                "--- group" => current = 1,
+                "--- entry" => current = 2,
+                _ => sections[current].push_str(line),
+            }
+        }
+        let [header, group, entry] = sections;
+        if entry.trim().is_empty() {
+            return Err("Template has no '--- entry' section".into());
+        }
+        Ok(Template { header, group, entry })
+    }
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Template::parse(DEFAULT_TEMPLATE).expect("valid built-in template")
+    }
+}
+
+/// Render entries logged at the commits of `range`. Commits come in `git log`
+/// order (newest first); entries within a group are in logging order. Commits
+/// without entries are left out.
+pub fn render(range: &str, commits: &[Commit], entries: &[LogEntry], group_by: GroupBy, template: &Template) -> String {
+    let by_sha: HashMap<&str, &Commit> = commits.iter().map(|c| (c.sha.as_str(), c)).collect();
+    let mut groups: Vec<(String, Vec<&LogEntry>)> = Vec::new();
+
+    match group_by {
+        GroupBy::Commit => {
+            for commit in commits {
+                let matching: Vec<&LogEntry> = entries.iter()
+                    .filter(|e| e.repo_commit.as_deref() == Some(commit.sha.as_str()))
+                    .collect();
+                if !matching.is_empty() {
+                    groups.push((format!("`{}` {}", commit.short, commit.subject), matching));
+                }
+            }
+        }
+        GroupBy::Session => {
+            for entry in entries {
+                let name = entry.name.clone().unwrap_or_else(|| "(unnamed)".to_string());
+                push_grouped(&mut groups, name, entry);
+            }
+        }
+        GroupBy::Day => {
+            let mut days: Vec<(NaiveDate, &LogEntry)> = entries.iter()
+                .map(|e| (e.timestamp.with_timezone(&Local).date_naive(), e))
+                .collect();
+            days.sort_by_key(|(day, _)| Reverse(*day));
+            for (day, entry) in days {
+                push_grouped(&mut groups, day.format("%Y-%m-%d").to_string(), entry);
+            }
+        }
+    }
+
+    let count = groups.iter().map(|(_, e)| e.len()).sum::<usize>();
+    let mut out = substitute(&template.header, &[("range", range), ("count", &count.to_string())]);
+    for (title, group_entries) in &groups {
+        out.push_str(&substitute(&template.group, &[
+            ("range", range),
+            ("title", title),
+            ("count", &group_entries.len().to_string()),
+        ]));
+        for entry in group_entries {
+            let commit = entry.repo_commit.as_deref().and_then(|sha| by_sha.get(sha));
+            let local = entry.timestamp.with_timezone(&Local);
+            out.push_str(&substitute(&template.entry, &[
+                ("range", range),
+                ("title", title),
+                ("message", &entry.message),
+                ("name", entry.name.as_deref().unwrap_or("(unnamed)")),
+                ("date", &local.format("%Y-%m-%d").to_string()),
+                ("time", &local.format("%H:%M").to_string()),
+                ("branch", entry.repo_branch.as_deref().unwrap_or("")),
+                ("commit", entry.repo_commit.as_deref().unwrap_or("")),
+                ("short_commit", commit.map(|c| c.short.as_str()).unwrap_or("")),
+                ("subject", commit.map(|c| c.subject.as_str()).unwrap_or("")),
+                ("commit_date", &commit.map(|c| c.author_time.with_timezone(&Local).format("%Y-%m-%d").to_string()).unwrap_or_default()),
+                ("event_id", entry.event_id.as_deref().unwrap_or("")),
+            ]));
+        }
+    }
+    out
+}
+
+/// Append to the group titled `title`, keeping groups in first-seen order
+fn push_grouped<'a>(groups: &mut Vec<(String, Vec<&'a LogEntry>)>, title: String, entry: &'a LogEntry) {
+    match groups.iter_mut().find(|(t, _)| *t == title) {
+        Some((_, group)) => group.push(entry),
+        None => groups.push((title, vec![entry])),
+    }
+}
+
+/// Replace `{key}` placeholders in a single pass, so substituted values are
+/// never expanded again
+fn substitute(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| {
+            values.iter().find(|(k, _)| *k == &after[..end]).map(|(_, v)| (*v, end))
+        });
+        match value {
+            Some((v, end)) => {
+                out.push_str(v);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn commit(sha: &str, subject: &str) -> Commit {
+        Commit {
+            sha: sha.to_string(),
+            short: sha[..3].to_string(),
+            subject: subject.to_string(),
+            author_time: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn entry(message: &str, name: &str, sha: &str) -> LogEntry {
+        LogEntry {
+            message: message.to_string(),
+            name: Some(name.to_string()),
+            repo_commit: Some(sha.to_string()),
+            timestamp: Utc.with_ymd_and_hms(2024, 5, 1, 11, 0, 0).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_sections() {
+        let template = Template::parse("# Log\n--- group\n## {title}\n--- entry\n- {message}\n").unwrap();
+        assert_eq!(template.header, "# Log\n");
+        assert_eq!(template.group, "## {title}\n");
+        assert_eq!(template.entry, "- {message}\n");
+
+        // Without a group section, entries follow the header directly
+        let flat = Template::parse("--- entry  \n{message}\n").unwrap();
+        assert_eq!((flat.header.as_str(), flat.group.as_str()), ("", ""));
+        assert!(Template::parse("# Log\n--- group\n## {title}\n").is_err());
+    }
+
+    #[test]
+    fn substitutes_in_one_pass() {
+        let values = [("a", "{b}"), ("b", "x")];
+        assert_eq!(substitute("{a} {b}", &values), "{b} x");
+        assert_eq!(substitute("{unknown} {a", &values), "{unknown} {a");
+        assert_eq!(substitute("{{b}}", &values), "{x}");
+    }
+
+    #[test]
+    fn renders_commit_groups_newest_first() {
+        let commits = [commit("ccc111", "third"), commit("bbb111", "second"), commit("aaa111", "first")];
+        let entries = [
+            entry("set up {name}", "alice", "aaa111"),
+            entry("fix", "bob", "ccc111"),
+            entry("more", "alice", "aaa111"),
+        ];
+        let template = Template::parse("{count} in {range}\n--- group\n## {title}\n--- entry\n- {message} ({name}, {short_commit}, {missing})\n").unwrap();
+        let out = render("v1..v2", &commits, &entries, GroupBy::Commit, &template);
+        assert_eq!(out, "\
+3 in v1..v2
+## `ccc` third
+- fix (bob, ccc, {missing})
+## `aaa` first
+- set up {name} (alice, aaa, {missing})
+- more (alice, aaa, {missing})
+");
+
+        let by_session = render("v1..v2", &commits, &entries, GroupBy::Session, &template);
+        assert!(by_session.starts_with("3 in v1..v2\n## alice\n- set up {name} (alice, aaa, {missing})\n- more"));
+    }
+}
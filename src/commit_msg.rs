@@ -0,0 +1,54 @@
+use std::fs;
+use std::path::Path;
+use crate::models::LogEntry;
+
+/// Trailer linking a commit to the entries its message was drafted from
+pub const TRAILER_KEY: &str = "Clog-Entries";
+
+/// Commit message from entries in logging order: the first entry is the
+/// summary line, later ones become a bullet list
+pub fn draft(entries: &[LogEntry]) -> Option<String> {
+    let (first, rest) = entries.split_first()?;
+    let mut msg = first.message.lines().next().unwrap_or("").trim().to_string();
+    if !rest.is_empty() {
+        msg.push('\n');
+        for entry in rest {
+            let mut lines = entry.message.trim().lines();
+            msg.push_str(&format!("\n- {}", lines.next().unwrap_or("")));
+            for line in lines {
+                msg.push_str(&format!("\n  {}", line));
+            }
+        }
+    }
+    msg.push('\n');
+    Some(msg)
+}
+
+/// `Clog-Entries: <event id>, ...`, or None when no entry has an event id
+pub fn trailer(entries: &[LogEntry]) -> Option<String> {
+    let ids: Vec<&str> = entries.iter().filter_map(|e| e.event_id.as_deref()).collect();
+    if ids.is_empty() {
+        return None;
+    }
+    Some(format!("{}: {}", TRAILER_KEY, ids.join(", ")))
+}
+
+/// Whether `prepare-commit-msg` should touch the message for this source.
+/// Merges, squashes and amends (`commit`) already carry a message about
+/// other work; `message` (`-m`) only gets the trailer.
+pub fn wants_trailer(source: Option<&str>) -> bool {
+    matches!(source, None | Some("") | Some("template") | Some("message"))
+}
+
+/// Whether the editor should be pre-filled with the draft
+pub fn wants_draft(source: Option<&str>) -> bool {
+    matches!(source, None | Some("") | Some("template"))
+}
+
+/// Put the draft above whatever git wrote into the message file (the
+/// template and the status comments)
+pub fn prefill(file: &Path, draft: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let existing = fs::read_to_string(file).unwrap_or_default();
+    fs::write(file, format!("{}{}", draft, existing))?;
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use dirs::home_dir;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Deserializer};
+
+const CONFIG_FILE: &str = ".clog/config.json";
+
+/// User configuration from `~/.clog/config.json`. Every section is optional;
+/// missing keys fall back to the built-in defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub session: SessionConfig,
+    pub git: GitConfig,
+    pub list: ListConfig,
+    pub log: LogConfig,
+    pub snapshot: SnapshotConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SnapshotConfig {
+    /// Largest diff `--snapshot` stores, in bytes
+    pub max_bytes: u64,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig { max_bytes: 1024 * 1024 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Capture the changed paths of the working tree with every entry (`--files`)
+    pub files: bool,
+    /// List recent entries after logging one (`--preview` / `--no-preview`)
+    pub preview: bool,
+    /// Hand entries to the background writer instead of waiting for the database (`--async`)
+    #[serde(rename = "async")]
+    pub async_log: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig { files: false, preview: true, async_log: false }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ListConfig {
+    /// Default `--reachable` when listing the current repository
+    pub reachable: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GitConfig {
+    /// Discover repositories with `git rev-parse` instead of reading `.git` directly
+    pub use_cli: bool,
+    /// Short names for `--repo`, mapping to a path, remote URL or repo id
+    pub repo_aliases: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// Ordered anchor rules; the first rule with a matching ancestor wins
+    pub anchors: Vec<AnchorRule>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig { anchors: default_anchor_rules() }
+    }
+}
+
+/// A rule selecting which ancestor process anchors the session.
+///
+/// All criteria that are set must match. `name` is matched case-insensitively
+/// against the process name, `exe` against the executable path and `cmdline`
+/// against the space-joined command line. Only ancestors up to `max_depth`
+/// levels above clog (parent = 1) are considered.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnchorRule {
+    #[serde(default, deserialize_with = "deserialize_regex_ci")]
+    pub name: Option<Regex>,
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub exe: Option<Regex>,
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub cmdline: Option<Regex>,
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+}
+
+impl AnchorRule {
+    fn named(pattern: &str) -> Self {
+        AnchorRule {
+            name: Some(RegexBuilder::new(pattern).case_insensitive(true).build().expect("valid built-in pattern")),
+            exe: None,
+            cmdline: None,
+            max_depth: default_max_depth(),
+        }
+    }
+
+    /// Human-readable summary of the rule's criteria
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(r) = &self.name { parts.push(format!("name=/{}/", r.as_str())); }
+        if let Some(r) = &self.exe { parts.push(format!("exe=/{}/", r.as_str())); }
+        if let Some(r) = &self.cmdline { parts.push(format!("cmdline=/{}/", r.as_str())); }
+        if parts.is_empty() { parts.push("any".to_string()); }
+        parts.push(format!("max_depth={}", self.max_depth));
+        parts.join(" ")
+    }
+}
+
+/// The historical heuristics: AI assistants (Claude Code runs in node) first,
+/// then the login shell
+fn default_anchor_rules() -> Vec<AnchorRule> {
+    vec![
+        AnchorRule::named("node|claude|codex|gemini"),
+        AnchorRule::named("^login$"),
+    ]
+}
+
+fn default_max_depth() -> usize {
+    20
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Regex>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .map(|p| Regex::new(&p).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn deserialize_regex_ci<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Regex>, D::Error> {
+    Option::<String>::deserialize(d)?
+        .map(|p| RegexBuilder::new(&p).case_insensitive(true).build().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+pub fn load() -> Result<Config, Box<dyn std::error::Error>> {
+    let path = get_config_path();
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    let content = fs::read_to_string(&path)?;
+    let config: Config = serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+    if config.git.use_cli && !cfg!(feature = "git-cli") {
+        eprintln!("Warning: git.use_cli is ignored; clog was built without the git-cli feature");
+    }
+    Ok(config)
+}
+
+pub fn get_config_path() -> PathBuf {
+    home_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join(CONFIG_FILE)
+}
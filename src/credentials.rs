@@ -4,6 +4,10 @@ use std::path::PathBuf;
 use dirs::home_dir;
 
 const CONFIG_FILE: &str = ".clog/config.json";
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "clog";
+#[cfg(feature = "keyring")]
+const KEYRING_USER: &str = "database_url";
 
 #[derive(Debug, Clone)]
 pub struct Credentials {
@@ -34,50 +38,18 @@ pub fn get_credentials() -> Result<Option<Credentials>, Box<dyn std::error::Erro
             }));
         }
     }
-    
-    Ok(None)
-}
 
-pub fn save_credentials(creds: &Credentials) -> Result<(), Box<dyn std::error::Error>> {
-    let config_path = get_config_path();
-    
-    // Ensure directory exists
-    if let Some(parent) = config_path.parent() {
-        fs::create_dir_all(parent)?;
-    }
-    
-    let config = serde_json::json!({
-        "database_url": creds.database_url,
-    });
-    
-    let content = serde_json::to_string_pretty(&config)?;
-    fs::write(&config_path, content)?;
-    
-    // Set restrictive permissions on config file
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let metadata = fs::metadata(&config_path)?;
-        let mut perms = metadata.permissions();
-        perms.set_mode(0o600);
-        fs::set_permissions(&config_path, perms)?;
+    // 4. OS keyring (service "clog", account "database_url"). An unusable
+    // keyring (no secret service on a headless machine, a locked keychain)
+    // must not stop clog from logging locally.
+    #[cfg(feature = "keyring")]
+    match keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).and_then(|entry| entry.get_password()) {
+        Ok(database_url) => return Ok(Some(Credentials { database_url })),
+        Err(keyring::Error::NoEntry) => {}
+        Err(e) => eprintln!("Warning: Could not read the database URL from the keyring: {}", e),
     }
-    
-    println!("✓ Database credentials saved to config file");
-    Ok(())
-}
 
-pub fn delete_credentials() -> Result<(), Box<dyn std::error::Error>> {
-    // Remove config file
-    let config_path = get_config_path();
-    if config_path.exists() {
-        fs::remove_file(&config_path)?;
-        println!("✓ Database credentials removed");
-    } else {
-        println!("No credentials found to remove");
-    }
-    
-    Ok(())
+    Ok(None)
 }
 
 fn get_config_path() -> PathBuf {
@@ -1,260 +1,276 @@
+use std::ops::Deref;
 use chrono::{DateTime, Utc};
-use native_tls::TlsConnector;
-use postgres_native_tls::MakeTlsConnector;
-use tokio_postgres::{Client, NoTls};
-use ulid::Ulid;
-use crate::models::{LogEntry, Session};
+use crate::models::{EntryFilter, LogEntry, RepoScope, Session, SessionLookup};
 
-pub struct Database {
-    rt: tokio::runtime::Runtime,
-    client: Client,
-    device_id: String,
-}
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 
-impl Database {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        // Load env from .env if present
-        dotenv::dotenv().ok();
-        let database_url = std::env::var("DATABASE_URL")
-            .map_err(|_| "DATABASE_URL not set. Put it in .env or env vars")?;
+#[cfg(not(any(feature = "postgres", feature = "sqlite")))]
+compile_error!("clog needs a storage backend: enable the `sqlite` or `postgres` feature");
+
+type DbResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// Storage for sessions and entries, implemented per database backend
+pub trait Store {
+    /// Backend and location, for `clog --info`
+    fn describe(&self) -> String;
 
-        let rt = tokio::runtime::Runtime::new()?;
-        let device_id = crate::device::get_or_create_device_id()?;
+    /// Find the active session anchored at this exact process (PID and start time).
+    /// Sessions live as long as the anchor process does; there is no idle expiry.
+    fn get_active_session(&self, ppid: u32, start_time: u64) -> DbResult<Option<Session>>;
+    /// Find the active session registered under an explicit key (`CLOG_SESSION`,
+    /// `CLOG_NAME` or `--as`). Keyed sessions do not expire after inactivity.
+    fn get_session_by_key(&self, key: &str) -> DbResult<Option<Session>>;
+    /// Create the session for a key, or return the active one if a concurrent
+    /// call got there first
+    fn create_keyed_session(&self, ppid: u32, key: &str, name: &str) -> DbResult<String>;
+    /// Create the session for an anchor process, or return the active one if a
+    /// concurrent call got there first. `name` (when given) is applied either way;
+    /// `auto_named` marks a derived name whose entries a later `--name` relabels.
+    fn create_session(&self, ppid: u32, start_time: u64, name: Option<&str>, auto_named: bool) -> DbResult<String>;
+    /// Rename a session. If it was auto-named, entries logged under the derived
+    /// name are relabeled too; returns how many were.
+    fn update_session_name(&self, session_id: &str, name: &str) -> DbResult<u64>;
+    fn update_session_last_seen(&self, session_id: &str) -> DbResult<()>;
+
+    /// Insert an entry under its event id (a new one when it has none) and
+    /// return the id. An id already in the database is skipped, so retrying
+    /// a write can't duplicate the entry.
+    fn insert_log_entry(&self, entry: &LogEntry) -> DbResult<String>;
+    /// Insert an entry (and its captured paths) into the active session found
+    /// by `lookup`, bumping the session's last_seen, in one round trip. The
+    /// entry takes the session's id and name; it is returned with its event id.
+    /// Writes nothing and returns None when there is no such session, or when
+    /// the entry's event id is already stored.
+    fn insert_into_session(&self, lookup: &SessionLookup, entry: &LogEntry, files: &[String]) -> DbResult<Option<LogEntry>>;
+    /// Record the working-tree paths captured with an entry
+    fn insert_entry_files(&self, event_id: &str, paths: &[String]) -> DbResult<()>;
+    /// Insert many entries at once: `COPY` on PostgreSQL, one transaction on
+    /// SQLite. Entries without an event id get a new one; ids already in the
+    /// database (e.g. from git notes) are skipped. Returns how many were new.
+    fn insert_log_entries(&self, entries: &[LogEntry]) -> DbResult<u64>;
+
+    /// The `limit` newest entries matching the filter, newest first
+    fn list_entries(&self, limit: usize, filter: &EntryFilter) -> DbResult<Vec<LogEntry>>;
+    /// Every entry matching the filter, oldest first
+    fn entries_matching(&self, filter: &EntryFilter) -> DbResult<Vec<LogEntry>>;
+    /// Entries of the repository linked to a file: captured with `path`, logged
+    /// at one of `commits` (the parents of commits that touched it) or listed
+    /// in `event_ids` (their `Clog-Entries` trailers). Oldest first; the flag
+    /// is set for entries that captured the path themselves.
+    fn entries_for_file(&self, repo: &RepoScope, path: &str, commits: &[String], event_ids: &[String]) -> DbResult<Vec<(LogEntry, bool)>>;
+    /// Distinct commits recorded on entries matching the filter
+    fn distinct_commits(&self, filter: &EntryFilter) -> DbResult<Vec<String>>;
+    /// Point entries at rewritten commits: each `(old, new)` pair moves the
+    /// entries logged at `old` within the repository to `new`, keeping the
+    /// first original sha. Returns the number of entries updated.
+    fn remap_commits(&self, repo: &RepoScope, pairs: &[(String, String)]) -> DbResult<u64>;
+    /// Entries stored after `after`, oldest first (for `--stream`)
+    fn list_entries_received_after(&self, after: DateTime<Utc>, filter: &EntryFilter) -> DbResult<Vec<LogEntry>>;
+}
 
-        // Connect to Postgres
-        let client = rt.block_on(async {
-            if database_url.contains("sslmode=require") {
-                let connector = TlsConnector::builder()
-                    .danger_accept_invalid_certs(true)
-                    .build()
-                    .map_err(|e| format!("TLS build error: {}", e))?;
-                let connector = MakeTlsConnector::new(connector);
-                let (client, connection) = tokio_postgres::connect(&database_url, connector).await?;
-                tokio::spawn(async move {
-                    if let Err(e) = connection.await {
-                        eprintln!("Postgres connection error: {}", e);
-                    }
-                });
-                Ok::<Client, Box<dyn std::error::Error>>(client)
-            } else {
-                let (client, connection) = tokio_postgres::connect(&database_url, NoTls).await?;
-                tokio::spawn(async move {
-                    if let Err(e) = connection.await {
-                        eprintln!("Postgres connection error: {}", e);
-                    }
-                });
-                Ok::<Client, Box<dyn std::error::Error>>(client)
+/// The database this build and configuration point at
+pub struct Database(Box<dyn Store>);
+
+impl Database {
+    /// PostgreSQL when a database URL is configured (`DATABASE_URL`, `.env`,
+    /// `database_url` in the config or the keyring), else the local SQLite file
+    pub fn new() -> DbResult<Self> {
+        let url = crate::credentials::get_credentials()?.map(|c| c.database_url);
+        match url {
+            #[cfg(feature = "postgres")]
+            Some(url) => Ok(Database(Box::new(postgres::PgStore::connect(&url)?))),
+            #[cfg(not(feature = "postgres"))]
+            Some(_) => {
+                eprintln!("Warning: DATABASE_URL is set but clog was built without the postgres feature; using the local database");
+                Self::local()
             }
-        })?;
+            None => Self::local(),
+        }
+    }
 
-        let mut db = Database { rt, client, device_id };
-        db.ensure_schema()?;
-        Ok(db)
+    #[cfg(feature = "sqlite")]
+    fn local() -> DbResult<Self> {
+        Ok(Database(Box::new(sqlite::SqliteStore::open(&sqlite::default_path())?)))
     }
 
-    fn ensure_schema(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        // Base schema (devices, log_entries, sync_state) from schema.sql
-        let schema = std::fs::read_to_string("schema.sql")
-            .unwrap_or_else(|_| include_str!("../schema.sql").to_string());
-        self.rt.block_on(self.client.batch_execute(&schema))?;
+    #[cfg(not(feature = "sqlite"))]
+    fn local() -> DbResult<Self> {
+        Err("DATABASE_URL not set. Put it in .env or env vars".into())
+    }
+}
 
-        // Add sessions table for per-device session tracking
-        self.rt.block_on(self.client.batch_execute(
-            r#"
-            CREATE TABLE IF NOT EXISTS sessions (
-                session_id TEXT PRIMARY KEY,
-                device_id TEXT NOT NULL,
-                ppid INTEGER NOT NULL,
-                name TEXT,
-                first_seen TIMESTAMPTZ NOT NULL,
-                last_seen TIMESTAMPTZ NOT NULL,
-                is_active BOOLEAN NOT NULL DEFAULT TRUE
-            );
-            CREATE INDEX IF NOT EXISTS idx_sessions_device_ppid_active ON sessions(device_id, ppid, is_active);
-            "#,
-        ))?;
+impl Deref for Database {
+    type Target = dyn Store;
 
-        Ok(())
+    fn deref(&self) -> &Self::Target {
+        &*self.0
     }
+}
+
+/// Column list matching each backend's `entry_from_row`
+const ENTRY_COLUMNS: &str = "ppid, name, timestamp, directory, message, session_id,
+    repo_root, repo_branch, repo_commit, event_id, received_at,
+    repo_staged, repo_unstaged, repo_untracked, repo_upstream, repo_ahead, repo_behind, repo_describe,
+    repo_id, repo_worktree, repo_superproject, kind, repo_commit_original, project_root, vcs, snapshot, repo_change";
 
-    pub fn get_active_session(&self, ppid: u32) -> Result<Option<Session>, Box<dyn std::error::Error>> {
-        let row = self.rt.block_on(self.client.query_opt(
-            "SELECT session_id, ppid, name, first_seen, last_seen, is_active
-             FROM sessions
-             WHERE device_id = $1 AND ppid = $2 AND is_active = TRUE
-               AND last_seen > (CURRENT_TIMESTAMP - INTERVAL '24 hours')
-             ORDER BY last_seen DESC
-             LIMIT 1",
-            &[&self.device_id, &(ppid as i32)],
-        ))?;
+/// A query parameter, converted to the driver's types by each backend
+#[derive(Debug, Clone)]
+enum Value {
+    Text(String),
+    Texts(Vec<String>),
+    Time(DateTime<Utc>),
+    Int(i64),
+}
 
-        let session = row.map(|r| Session {
-            session_id: r.get::<_, String>(0),
-            ppid: r.get::<_, i32>(1) as u32,
-            name: r.get(2),
-            first_seen: r.get(3),
-            last_seen: r.get(4),
-            is_active: r.get(5),
+/// Where the SQL of the backends differs
+trait Dialect {
+    /// Placeholder for the n-th parameter (1-based)
+    fn param(&self, n: usize) -> String;
+    /// `column` equals one of the strings in array parameter `n`
+    fn any(&self, column: &str, n: usize) -> String;
+    /// `column` matches the regex in parameter `n`
+    fn regex(&self, column: &str, n: usize) -> String;
+    /// `column` falls on the current date
+    fn today(&self, column: &str) -> String;
+}
+
+/// Append the filter's conditions. Parameter 1 must be this device's id:
+/// without a repository scope only this device's entries match.
+fn push_filters(sql: &mut String, params: &mut Vec<Value>, filter: &EntryFilter, d: &dyn Dialect) {
+    match &filter.repo {
+        Some(repo) => push_repo_scope(sql, params, repo, d),
+        None => sql.push_str(&format!(" AND device_id = {}", d.param(1))),
+    }
+    if let Some(project) = &filter.project {
+        push_condition(sql, params, Value::Text(project.clone()), |n| format!("project_root = {}", d.param(n)));
+    }
+    if let Some(name) = &filter.name {
+        push_condition(sql, params, Value::Text(name.clone()), |n| format!("name = {}", d.param(n)));
+    }
+    if let Some(sid) = &filter.session_id {
+        push_condition(sql, params, Value::Text(sid.clone()), |n| format!("session_id = {}", d.param(n)));
+    }
+    if let Some(wt) = &filter.worktree {
+        push_condition(sql, params, Value::Text(wt.clone()), |n| format!("COALESCE(repo_worktree, repo_root) = {}", d.param(n)));
+    }
+    if let Some(b) = &filter.branch {
+        push_condition(sql, params, Value::Text(b.clone()), |n| format!("repo_branch = {}", d.param(n)));
+    }
+    if let Some(prefix) = &filter.commit_prefix {
+        // Entries remapped after a rewrite still answer to the original sha,
+        // and jj entries to their change id
+        push_condition(sql, params, Value::Text(format!("{}%", prefix)), |n| {
+            format!("(repo_commit LIKE {p} OR repo_commit_original LIKE {p} OR repo_change LIKE {p})", p = d.param(n))
         });
-        Ok(session)
     }
-
-    pub fn create_session(&self, ppid: u32) -> Result<String, Box<dyn std::error::Error>> {
-        let now = Utc::now();
-        let session_id = format!("{}_{}", ppid, now.timestamp());
-        self.rt.block_on(self.client.execute(
-            "INSERT INTO sessions (session_id, device_id, ppid, first_seen, last_seen, is_active)
-             VALUES ($1, $2, $3, $4, $5, TRUE)",
-            &[&session_id, &self.device_id, &(ppid as i32), &now, &now],
-        ))?;
-        Ok(session_id)
+    if let Some(commits) = &filter.commits {
+        push_condition(sql, params, Value::Texts(commits.clone()), |n| d.any("repo_commit", n));
     }
-
-    pub fn update_session_name(&self, session_id: &str, name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let now = Utc::now();
-        self.rt.block_on(self.client.execute(
-            "UPDATE sessions SET name = $1, last_seen = $2 WHERE session_id = $3 AND device_id = $4",
-            &[&name, &now, &session_id, &self.device_id],
-        ))?;
-        Ok(())
+    if filter.notes_only {
+        sql.push_str(" AND kind IS NULL");
     }
-
-    pub fn update_session_last_seen(&self, session_id: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let now = Utc::now();
-        self.rt.block_on(self.client.execute(
-            "UPDATE sessions SET last_seen = $1 WHERE session_id = $2 AND device_id = $3",
-            &[&now, &session_id, &self.device_id],
-        ))?;
-        Ok(())
+    if let Some(glob) = &filter.path {
+        push_condition(sql, params, Value::Text(glob_to_regex(glob)), |n| {
+            format!("EXISTS (SELECT 1 FROM entry_files f WHERE f.event_id = log_entries.event_id AND {})", d.regex("f.path", n))
+        });
     }
-
-    pub fn insert_log_entry(&self, entry: &LogEntry) -> Result<(), Box<dyn std::error::Error>> {
-        let event_id = Ulid::new().to_string();
-        self.rt.block_on(self.client.execute(
-            "INSERT INTO log_entries (
-                event_id, device_id, ppid, name, timestamp, directory, message, session_id,
-                repo_root, repo_branch, repo_commit
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
-            &[
-                &event_id,
-                &self.device_id,
-                &(entry.ppid as i32),
-                &entry.name,
-                &entry.timestamp,
-                &entry.directory,
-                &entry.message,
-                &entry.session_id,
-                &entry.repo_root,
-                &entry.repo_branch,
-                &entry.repo_commit,
-            ],
-        ))?;
-        Ok(())
+    if let Some(since) = &filter.since {
+        push_condition(sql, params, Value::Time(*since), |n| format!("timestamp >= {}", d.param(n)));
+    }
+    if filter.today {
+        sql.push_str(" AND ");
+        sql.push_str(&d.today("timestamp"));
     }
+}
 
-    pub fn list_entries(
-        &self,
-        limit: usize,
-        repo_root: Option<&str>,
-        filter_name: Option<&str>,
-        today_only: bool,
-        session_id: Option<&str>,
-    ) -> Result<Vec<LogEntry>, Box<dyn std::error::Error>> {
-        let mut sql = String::from(
-            "SELECT ppid, name, timestamp, directory, message, session_id,
-                    repo_root, repo_branch, repo_commit, event_id, received_at
-             FROM log_entries WHERE device_id = $1",
-        );
-        let mut params: Vec<Box<dyn tokio_postgres::types::ToSql + Sync>> = Vec::new();
-        params.push(Box::new(self.device_id.clone()));
+/// Append ` AND <condition>` with `value` as the next parameter
+fn push_condition(sql: &mut String, params: &mut Vec<Value>, value: Value, condition: impl FnOnce(usize) -> String) {
+    params.push(value);
+    sql.push_str(" AND ");
+    sql.push_str(&condition(params.len()));
+}
 
-        if let Some(root) = repo_root {
-            sql.push_str(" AND repo_root = $"); sql.push_str(&(params.len()+1).to_string());
-            params.push(Box::new(root.to_string()));
-        }
-        if let Some(n) = filter_name {
-            sql.push_str(" AND name = $"); sql.push_str(&(params.len()+1).to_string());
-            params.push(Box::new(n.to_string()));
-        }
-        if let Some(sid) = session_id {
-            sql.push_str(" AND session_id = $"); sql.push_str(&(params.len()+1).to_string());
-            params.push(Box::new(sid.to_string()));
-        }
-        if today_only {
-            sql.push_str(" AND timestamp::date = CURRENT_DATE");
-        }
-        sql.push_str(" ORDER BY timestamp DESC LIMIT $");
-        sql.push_str(&(params.len()+1).to_string());
-        params.push(Box::new(limit as i64));
+/// Entries with the repo id, from any device (clones elsewhere share it), or
+/// with the root path on this device (parameter 1), which also catches
+/// entries logged before ids existed. Before worktrees were told apart, an
+/// entry from a linked worktree recorded that worktree as its root, so those
+/// paths count as the root too.
+fn push_repo_scope(sql: &mut String, params: &mut Vec<Value>, repo: &RepoScope, d: &dyn Dialect) {
+    let device = format!("device_id = {}", d.param(1));
+    let mut terms = Vec::new();
+    if let Some(id) = &repo.id {
+        params.push(Value::Text(id.clone()));
+        terms.push(format!("repo_id = {}", d.param(params.len())));
+    }
+    if let Some(root) = &repo.root {
+        let roots = std::iter::once(root).chain(&repo.worktrees).cloned().collect();
+        params.push(Value::Texts(roots));
+        terms.push(format!("({} AND {})", device, d.any("repo_root", params.len())));
+    }
+    if terms.is_empty() {
+        terms.push(device);
+    }
+    sql.push_str(&format!(" AND ({})", terms.join(" OR ")));
+}
 
-        let rows = self.rt.block_on(self.client.query(&sql, &params.iter().map(|p| &**p).collect::<Vec<_>>()))?;
-        let entries = rows.into_iter().map(|r| LogEntry {
-            id: None,
-            ppid: r.get::<_, i32>(0) as u32,
-            name: r.get(1),
-            timestamp: r.get(2),
-            directory: r.get(3),
-            message: r.get(4),
-            session_id: r.get(5),
-            repo_root: r.get(6),
-            repo_branch: r.get(7),
-            repo_commit: r.get(8),
-            event_id: r.get(9),
-            received_at: r.get(10),
-        }).collect();
-        Ok(entries)
+/// Anchored regex for a path glob: `*` and `?` stay within one directory,
+/// `**` crosses them. A glob without `/` matches the file name at any depth.
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    if !glob.contains('/') {
+        re.push_str("(.*/)?");
+    }
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // `**/` also matches no directory at all
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re.push_str("(.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            // Both dialects read `\` before ASCII punctuation as the literal
+            // character; `\<` and `\>` are word boundaries in Rust's syntax
+            c if c.is_ascii_punctuation() && !matches!(c, '/' | '_' | '-' | '<' | '>') => {
+                re.push('\\');
+                re.push(c);
+            }
+            c => re.push(c),
+        }
     }
+    re.push('$');
+    re
+}
 
-    pub fn list_entries_received_after(
-        &self,
-        after: DateTime<Utc>,
-        repo_root: Option<&str>,
-        filter_name: Option<&str>,
-        today_only: bool,
-        session_id: Option<&str>,
-    ) -> Result<Vec<LogEntry>, Box<dyn std::error::Error>> {
-        let mut sql = String::from(
-            "SELECT ppid, name, timestamp, directory, message, session_id,
-                    repo_root, repo_branch, repo_commit, event_id, received_at
-             FROM log_entries WHERE device_id = $1 AND received_at > $2",
-        );
-        let mut params: Vec<Box<dyn tokio_postgres::types::ToSql + Sync>> = Vec::new();
-        params.push(Box::new(self.device_id.clone()));
-        params.push(Box::new(after));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if let Some(root) = repo_root {
-            sql.push_str(" AND repo_root = $"); sql.push_str(&(params.len()+1).to_string());
-            params.push(Box::new(root.to_string()));
-        }
-        if let Some(n) = filter_name {
-            sql.push_str(" AND name = $"); sql.push_str(&(params.len()+1).to_string());
-            params.push(Box::new(n.to_string()));
-        }
-        if let Some(sid) = session_id {
-            sql.push_str(" AND session_id = $"); sql.push_str(&(params.len()+1).to_string());
-            params.push(Box::new(sid.to_string()));
-        }
-        if today_only {
-            sql.push_str(" AND timestamp::date = CURRENT_DATE");
-        }
-        sql.push_str(" ORDER BY received_at ASC");
+    fn matches(glob: &str, path: &str) -> bool {
+        regex::Regex::new(&glob_to_regex(glob)).unwrap().is_match(path)
+    }
 
-        let rows = self.rt.block_on(self.client.query(&sql, &params.iter().map(|p| &**p).collect::<Vec<_>>()))?;
-        let entries = rows.into_iter().map(|r| LogEntry {
-            id: None,
-            ppid: r.get::<_, i32>(0) as u32,
-            name: r.get(1),
-            timestamp: r.get(2),
-            directory: r.get(3),
-            message: r.get(4),
-            session_id: r.get(5),
-            repo_root: r.get(6),
-            repo_branch: r.get(7),
-            repo_commit: r.get(8),
-            event_id: r.get(9),
-            received_at: r.get(10),
-        }).collect();
-        Ok(entries)
+    #[test]
+    fn path_globs() {
+        assert!(matches("db.rs", "src/db.rs"));
+        assert!(matches("*.rs", "src/db.rs"));
+        assert!(!matches("src/*.rs", "src/git/mod.rs"));
+        assert!(matches("src/**/*.rs", "src/git/mod.rs"));
+        assert!(matches("src/**/*.rs", "src/db.rs"));
+        assert!(!matches("db.rs", "src/db_rs"));
+        assert!(matches("d?.rs", "db.rs"));
+        assert!(matches("naïve notes.rs", "docs/naïve notes.rs"));
+        assert!(matches("*é*", "src/café.rs"));
+        assert!(!matches("naïve notes.rs", "naive notes.rs"));
+        assert!(matches("a+b (1)<2>.{rs}", "a+b (1)<2>.{rs}"));
     }
 }
-
@@ -0,0 +1,651 @@
+use std::fs;
+use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use dirs::home_dir;
+#[cfg(feature = "tls")]
+use native_tls::TlsConnector;
+#[cfg(feature = "tls")]
+use postgres_native_tls::MakeTlsConnector;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::Type;
+use tokio_postgres::{Client, NoTls};
+use sha2::{Digest, Sha256};
+use ulid::Ulid;
+use crate::models::{EntryFilter, LogEntry, RepoScope, Session, SessionLookup};
+use super::{push_filters, push_repo_scope, DbResult, Dialect, Store, Value, ENTRY_COLUMNS};
+
+fn entry_from_row(r: &tokio_postgres::Row) -> LogEntry {
+    let count = |i: usize| r.get::<_, Option<i32>>(i).map(|n| n as u32);
+    LogEntry {
+        ppid: r.get::<_, i32>(0) as u32,
+        name: r.get(1),
+        timestamp: r.get(2),
+        directory: r.get(3),
+        message: r.get(4),
+        session_id: r.get(5),
+        repo_root: r.get(6),
+        repo_branch: r.get(7),
+        repo_commit: r.get(8),
+        event_id: r.get(9),
+        received_at: r.get(10),
+        repo_staged: count(11),
+        repo_unstaged: count(12),
+        repo_untracked: count(13),
+        repo_upstream: r.get(14),
+        repo_ahead: count(15),
+        repo_behind: count(16),
+        repo_describe: r.get(17),
+        repo_id: r.get(18),
+        repo_worktree: r.get(19),
+        repo_superproject: r.get(20),
+        kind: r.get(21),
+        repo_commit_original: r.get(22),
+        project_root: r.get(23),
+        vcs: r.get(24),
+        snapshot: r.get(25),
+        repo_change: r.get(26),
+    }
+}
+
+/// Columns written for a new entry; the session's id and name come last
+const INSERT_COLUMNS: &str = "event_id, device_id, ppid, timestamp, directory, message,
+    repo_root, repo_branch, repo_commit,
+    repo_staged, repo_unstaged, repo_untracked, repo_upstream, repo_ahead, repo_behind, repo_describe,
+    repo_id, repo_worktree, repo_superproject, kind, project_root, vcs, snapshot, repo_change,
+    session_id, name";
+
+/// Types of `INSERT_COLUMNS`, for binary `COPY`
+const INSERT_TYPES: [Type; 26] = [
+    Type::TEXT, Type::TEXT, Type::INT4, Type::TIMESTAMPTZ, Type::TEXT, Type::TEXT,
+    Type::TEXT, Type::TEXT, Type::TEXT,
+    Type::INT4, Type::INT4, Type::INT4, Type::TEXT, Type::INT4, Type::INT4, Type::TEXT,
+    Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT,
+    Type::TEXT, Type::TEXT,
+];
+
+type SqlParams = Vec<Box<dyn tokio_postgres::types::ToSql + Sync>>;
+
+fn to_sql(params: Vec<Value>) -> SqlParams {
+    params
+        .into_iter()
+        .map(|v| -> Box<dyn tokio_postgres::types::ToSql + Sync> {
+            match v {
+                Value::Text(s) => Box::new(s),
+                Value::Texts(list) => Box::new(list),
+                Value::Time(t) => Box::new(t),
+                Value::Int(n) => Box::new(n),
+            }
+        })
+        .collect()
+}
+
+struct PgDialect;
+
+impl Dialect for PgDialect {
+    fn param(&self, n: usize) -> String {
+        format!("${}", n)
+    }
+
+    fn any(&self, column: &str, n: usize) -> String {
+        format!("{} = ANY(${})", column, n)
+    }
+
+    fn regex(&self, column: &str, n: usize) -> String {
+        format!("{} ~ ${}", column, n)
+    }
+
+    fn today(&self, column: &str) -> String {
+        format!("{}::date = CURRENT_DATE", column)
+    }
+}
+
+/// Sessions table for per-device session tracking, and the migration log
+const SESSIONS_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS sessions (
+    session_id TEXT PRIMARY KEY,
+    device_id TEXT NOT NULL,
+    ppid INTEGER NOT NULL,
+    name TEXT,
+    first_seen TIMESTAMPTZ NOT NULL,
+    last_seen TIMESTAMPTZ NOT NULL,
+    is_active BOOLEAN NOT NULL DEFAULT TRUE
+);
+CREATE INDEX IF NOT EXISTS idx_sessions_device_ppid_active ON sessions(device_id, ppid, is_active);
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS session_key TEXT;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS anchor_start BIGINT;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS auto_named BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE INDEX IF NOT EXISTS idx_sessions_device_key_active ON sessions(device_id, session_key, is_active);
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER PRIMARY KEY,
+    applied_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+"#;
+
+/// Newest data migration in `ensure_schema`; bump with each new one
+const LATEST_MIGRATION: i32 = 2;
+
+/// Base schema (devices, log_entries, sync_state) from schema.sql, then sessions
+fn schema_sql() -> String {
+    let base = std::fs::read_to_string("schema.sql")
+        .unwrap_or_else(|_| include_str!("../../schema.sql").to_string());
+    format!("{}\n{}", base, SESSIONS_SCHEMA)
+}
+
+/// Databases known to have a given schema, as lines of `<url hash> <schema
+/// hash>` in `~/.clog/schema_checked`. Delete the file to force a check.
+struct SchemaCheck<'a> {
+    database_url: &'a str,
+    schema: &'a str,
+}
+
+impl SchemaCheck<'_> {
+    fn path() -> PathBuf {
+        home_dir()
+            .unwrap_or_else(|| PathBuf::from("/tmp"))
+            .join(".clog")
+            .join("schema_checked")
+    }
+
+    fn hashes(&self) -> (String, String) {
+        let hash = |s: &str| Sha256::digest(s).iter().take(8).map(|b| format!("{:02x}", b)).collect::<String>();
+        (hash(self.database_url), hash(&format!("{}\n{}", self.schema, LATEST_MIGRATION)))
+    }
+
+    fn is_known(&self) -> bool {
+        let (url, schema) = self.hashes();
+        fs::read_to_string(Self::path())
+            .map(|s| s.lines().any(|l| l == format!("{} {}", url, schema)))
+            .unwrap_or(false)
+    }
+
+    /// Best effort: without the file the check just runs again next time
+    fn remember(&self) {
+        let (url, schema) = self.hashes();
+        let mut lines: Vec<String> = fs::read_to_string(Self::path())
+            .unwrap_or_default()
+            .lines()
+            .filter(|l| !l.starts_with(&url))
+            .map(str::to_string)
+            .collect();
+        lines.push(format!("{} {}", url, schema));
+        let _ = fs::write(Self::path(), lines.join("\n") + "\n");
+    }
+}
+
+const MIGRATION_LOCK_ID: i64 = 0x636c_6f67; // "clog"
+
+pub struct PgStore {
+    rt: tokio::runtime::Runtime,
+    client: Client,
+    device_id: String,
+}
+
+impl PgStore {
+    pub fn connect(database_url: &str) -> DbResult<Self> {
+        // Every call blocks on the runtime, so one thread (no worker pool) does
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let device_id = crate::device::get_or_create_device_id()?;
+
+        // Connect to Postgres
+        let client = rt.block_on(async {
+            if database_url.contains("sslmode=require") {
+                #[cfg(feature = "tls")]
+                {
+                    let connector = TlsConnector::builder()
+                        .danger_accept_invalid_certs(true)
+                        .build()
+                        .map_err(|e| format!("TLS build error: {}", e))?;
+                    let connector = MakeTlsConnector::new(connector);
+                    let (client, connection) = tokio_postgres::connect(database_url, connector).await?;
+                    tokio::spawn(async move {
+                        if let Err(e) = connection.await {
+                            eprintln!("Postgres connection error: {}", e);
+                        }
+                    });
+                    Ok::<Client, Box<dyn std::error::Error>>(client)
+                }
+                #[cfg(not(feature = "tls"))]
+                Err("sslmode=require needs a clog built with the tls feature".into())
+            } else {
+                let (client, connection) = tokio_postgres::connect(database_url, NoTls).await?;
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        eprintln!("Postgres connection error: {}", e);
+                    }
+                });
+                Ok::<Client, Box<dyn std::error::Error>>(client)
+            }
+        })?;
+
+        let mut db = PgStore { rt, client, device_id };
+        // The DDL transaction is the slowest part of a write; once this
+        // schema has been applied to this database, skip it
+        let schema = schema_sql();
+        let checked = SchemaCheck { database_url, schema: &schema };
+        if !checked.is_known() {
+            db.ensure_schema(&schema)?;
+            checked.remember();
+        }
+        Ok(db)
+    }
+
+    /// Create or upgrade the schema. Runs in one transaction under an advisory
+    /// lock so concurrent first runs don't deadlock on DDL.
+    fn ensure_schema(&mut self, schema: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.rt.block_on(async {
+            let tx = self.client.transaction().await?;
+            tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_ID]).await?;
+            tx.batch_execute(schema).await?;
+
+            // Versioned data migrations
+            let version: i32 = tx.query_one("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", &[]).await?.get(0);
+            if version < 1 {
+                migrate_v1_session_ids(&tx).await?;
+                tx.execute("INSERT INTO schema_migrations (version) VALUES (1)", &[]).await?;
+            }
+            if version < 2 {
+                // jj entries stored the change id in repo_commit; the git commit wasn't recorded
+                tx.batch_execute(
+                    "UPDATE log_entries SET repo_change = repo_commit, repo_commit = NULL WHERE vcs = 'jj';
+                     INSERT INTO schema_migrations (version) VALUES (2);",
+                )
+                .await?;
+            }
+
+            tx.commit().await?;
+            Ok::<(), Box<dyn std::error::Error>>(())
+        })
+    }
+
+    /// Values for `INSERT_COLUMNS` up to (not including) the session id and name
+    fn entry_values(&self, event_id: &str, entry: &LogEntry) -> SqlParams {
+        let count = |n: Option<u32>| n.map(|n| n as i32);
+        vec![
+            Box::new(event_id.to_string()),
+            Box::new(self.device_id.clone()),
+            Box::new(entry.ppid as i32),
+            Box::new(entry.timestamp),
+            Box::new(entry.directory.clone()),
+            Box::new(entry.message.clone()),
+            Box::new(entry.repo_root.clone()),
+            Box::new(entry.repo_branch.clone()),
+            Box::new(entry.repo_commit.clone()),
+            Box::new(count(entry.repo_staged)),
+            Box::new(count(entry.repo_unstaged)),
+            Box::new(count(entry.repo_untracked)),
+            Box::new(entry.repo_upstream.clone()),
+            Box::new(count(entry.repo_ahead)),
+            Box::new(count(entry.repo_behind)),
+            Box::new(entry.repo_describe.clone()),
+            Box::new(entry.repo_id.clone()),
+            Box::new(entry.repo_worktree.clone()),
+            Box::new(entry.repo_superproject.clone()),
+            Box::new(entry.kind.clone()),
+            Box::new(entry.project_root.clone()),
+            Box::new(entry.vcs.clone()),
+            Box::new(entry.snapshot.clone()),
+            Box::new(entry.repo_change.clone()),
+        ]
+    }
+
+    fn insert_entry(&self, event_id: &str, entry: &LogEntry, on_conflict: &str) -> DbResult<u64> {
+        let mut params = self.entry_values(event_id, entry);
+        params.push(Box::new(entry.session_id.clone()));
+        params.push(Box::new(entry.name.clone()));
+        let sql = format!(
+            "INSERT INTO log_entries ({}) VALUES ({}){}",
+            INSERT_COLUMNS,
+            (1..=params.len()).map(|n| format!("${}", n)).collect::<Vec<_>>().join(", "),
+            on_conflict
+        );
+        let inserted = self.rt.block_on(self.client.execute(&sql, &params.iter().map(|p| &**p).collect::<Vec<_>>()))?;
+        Ok(inserted)
+    }
+
+    fn query(&self, sql: &str, params: Vec<Value>) -> DbResult<Vec<tokio_postgres::Row>> {
+        let params = to_sql(params);
+        Ok(self.rt.block_on(self.client.query(sql, &params.iter().map(|p| &**p).collect::<Vec<_>>()))?)
+    }
+}
+
+impl Store for PgStore {
+    fn describe(&self) -> String {
+        "PostgreSQL".to_string()
+    }
+
+    fn get_active_session(&self, ppid: u32, start_time: u64) -> DbResult<Option<Session>> {
+        let row = self.rt.block_on(self.client.query_opt(
+            "SELECT session_id, name
+             FROM sessions
+             WHERE device_id = $1 AND ppid = $2 AND anchor_start = $3 AND is_active = TRUE
+             ORDER BY last_seen DESC
+             LIMIT 1",
+            &[&self.device_id, &(ppid as i32), &(start_time as i64)],
+        ))?;
+
+        let session = row.map(|r| Session { session_id: r.get(0), name: r.get(1) });
+        Ok(session)
+    }
+
+    fn get_session_by_key(&self, key: &str) -> DbResult<Option<Session>> {
+        let row = self.rt.block_on(self.client.query_opt(
+            "SELECT session_id, name
+             FROM sessions
+             WHERE device_id = $1 AND session_key = $2 AND is_active = TRUE
+             ORDER BY last_seen DESC
+             LIMIT 1",
+            &[&self.device_id, &key],
+        ))?;
+
+        let session = row.map(|r| Session { session_id: r.get(0), name: r.get(1) });
+        Ok(session)
+    }
+
+    fn create_keyed_session(&self, ppid: u32, key: &str, name: &str) -> DbResult<String> {
+        let now = Utc::now();
+        let session_id = Ulid::new().to_string();
+        let row = self.rt.block_on(self.client.query_one(
+            "INSERT INTO sessions (session_id, device_id, ppid, name, session_key, first_seen, last_seen, is_active)
+             VALUES ($1, $2, $3, $4, $5, $6, $6, TRUE)
+             ON CONFLICT (device_id, session_key) WHERE is_active AND session_key IS NOT NULL
+             DO UPDATE SET last_seen = EXCLUDED.last_seen
+             RETURNING session_id",
+            &[&session_id, &self.device_id, &(ppid as i32), &name, &key, &now],
+        ))?;
+        Ok(row.get(0))
+    }
+
+    fn create_session(&self, ppid: u32, start_time: u64, name: Option<&str>, auto_named: bool) -> DbResult<String> {
+        let now = Utc::now();
+        let session_id = Ulid::new().to_string();
+        // Any other active session on this PID belonged to an earlier process
+        let row = self.rt.block_on(self.client.query_one(
+            "WITH retired AS (
+                UPDATE sessions SET is_active = FALSE
+                WHERE device_id = $2 AND ppid = $3 AND session_key IS NULL
+                  AND anchor_start IS DISTINCT FROM $4 AND is_active = TRUE
+             )
+             INSERT INTO sessions (session_id, device_id, ppid, anchor_start, name, auto_named, first_seen, last_seen, is_active)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $7, TRUE)
+             ON CONFLICT (device_id, ppid, anchor_start) WHERE is_active AND session_key IS NULL
+             DO UPDATE SET last_seen = EXCLUDED.last_seen,
+                           name = COALESCE(EXCLUDED.name, sessions.name),
+                           auto_named = CASE WHEN EXCLUDED.name IS NULL THEN sessions.auto_named ELSE EXCLUDED.auto_named END
+             RETURNING session_id",
+            &[&session_id, &self.device_id, &(ppid as i32), &(start_time as i64), &name, &auto_named, &now],
+        ))?;
+        Ok(row.get(0))
+    }
+
+    fn update_session_name(&self, session_id: &str, name: &str) -> DbResult<u64> {
+        let now = Utc::now();
+        let relabeled = self.rt.block_on(self.client.execute(
+            "WITH prev AS (
+                SELECT name, auto_named FROM sessions WHERE session_id = $3 AND device_id = $4
+             ), renamed AS (
+                UPDATE sessions SET name = $1, last_seen = $2, auto_named = FALSE
+                WHERE session_id = $3 AND device_id = $4
+             )
+             UPDATE log_entries SET name = $1
+             WHERE session_id = $3 AND device_id = $4
+               AND EXISTS (SELECT 1 FROM prev WHERE prev.auto_named AND prev.name IS NOT DISTINCT FROM log_entries.name)",
+            &[&name, &now, &session_id, &self.device_id],
+        ))?;
+        Ok(relabeled)
+    }
+
+    fn update_session_last_seen(&self, session_id: &str) -> DbResult<()> {
+        let now = Utc::now();
+        self.rt.block_on(self.client.execute(
+            "UPDATE sessions SET last_seen = $1 WHERE session_id = $2 AND device_id = $3",
+            &[&now, &session_id, &self.device_id],
+        ))?;
+        Ok(())
+    }
+
+    fn insert_log_entry(&self, entry: &LogEntry) -> DbResult<String> {
+        let event_id = entry.event_id.clone().unwrap_or_else(|| Ulid::new().to_string());
+        self.insert_entry(&event_id, entry, " ON CONFLICT (event_id) DO NOTHING")?;
+        Ok(event_id)
+    }
+
+    fn insert_into_session(&self, lookup: &SessionLookup, entry: &LogEntry, files: &[String]) -> DbResult<Option<LogEntry>> {
+        let event_id = entry.event_id.clone().unwrap_or_else(|| Ulid::new().to_string());
+        let mut params = self.entry_values(&event_id, entry);
+        let values = (1..=params.len()).map(|n| format!("${}", n)).collect::<Vec<_>>().join(", ");
+        let mut param = |value: Box<dyn tokio_postgres::types::ToSql + Sync>| {
+            params.push(value);
+            format!("${}", params.len())
+        };
+        let device = "$2";
+        let matches = match lookup {
+            SessionLookup::Anchor { ppid, start_time } => {
+                format!("ppid = {} AND anchor_start = {}", param(Box::new(*ppid as i32)), param(Box::new(*start_time as i64)))
+            }
+            SessionLookup::Key { key, name } => {
+                let key = param(Box::new(key.clone()));
+                match name {
+                    Some(name) => format!("session_key = {} AND name = {}", key, param(Box::new(name.clone()))),
+                    None => format!("session_key = {}", key),
+                }
+            }
+        };
+        let now = param(Box::new(Utc::now()));
+        let files = param(Box::new(files.to_vec()));
+        // Session update, entry and paths in one statement: the entry only
+        // exists if the session does
+        let sql = format!(
+            "WITH s AS (
+                UPDATE sessions SET last_seen = {now}
+                WHERE session_id = (
+                    SELECT session_id FROM sessions
+                    WHERE device_id = {device} AND {matches} AND is_active = TRUE
+                    ORDER BY last_seen DESC LIMIT 1)
+                RETURNING session_id, name
+             ), e AS (
+                INSERT INTO log_entries ({INSERT_COLUMNS})
+                SELECT {values}, s.session_id, s.name FROM s
+                ON CONFLICT (event_id) DO NOTHING
+                RETURNING event_id, session_id, name
+             ), f AS (
+                INSERT INTO entry_files (event_id, path)
+                SELECT e.event_id, p FROM e, unnest({files}::text[]) AS p ON CONFLICT DO NOTHING
+             )
+             SELECT session_id, name FROM e"
+        );
+        let row = self.rt.block_on(self.client.query_opt(&sql, &params.iter().map(|p| &**p).collect::<Vec<_>>()))?;
+        Ok(row.map(|r| LogEntry {
+            session_id: r.get(0),
+            name: r.get(1),
+            event_id: Some(event_id),
+            ..entry.clone()
+        }))
+    }
+
+    fn insert_entry_files(&self, event_id: &str, paths: &[String]) -> DbResult<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.rt.block_on(self.client.execute(
+            "INSERT INTO entry_files (event_id, path) SELECT $1, p FROM unnest($2::text[]) AS p ON CONFLICT DO NOTHING",
+            &[&event_id, &paths],
+        ))?;
+        Ok(())
+    }
+
+    fn insert_log_entries(&self, entries: &[LogEntry]) -> DbResult<u64> {
+        if entries.is_empty() {
+            return Ok(0);
+        }
+        // COPY can't skip duplicates, so stream into a per-connection staging
+        // table; the one INSERT from it is atomic and skips known event ids
+        self.rt.block_on(async {
+            self.client.batch_execute(&format!(
+                "CREATE TEMP TABLE IF NOT EXISTS incoming_entries AS SELECT {INSERT_COLUMNS} FROM log_entries WITH NO DATA;
+                 TRUNCATE incoming_entries"
+            )).await?;
+
+            let sink = self.client.copy_in(&format!("COPY incoming_entries ({INSERT_COLUMNS}) FROM STDIN (FORMAT binary)")).await?;
+            let mut writer = std::pin::pin!(BinaryCopyInWriter::new(sink, &INSERT_TYPES));
+            for entry in entries {
+                let event_id = entry.event_id.clone().unwrap_or_else(|| Ulid::new().to_string());
+                let mut row = self.entry_values(&event_id, entry);
+                row.push(Box::new(entry.session_id.clone()));
+                row.push(Box::new(entry.name.clone()));
+                writer.as_mut().write(&row.iter().map(|v| &**v).collect::<Vec<_>>()).await?;
+            }
+            writer.finish().await?;
+
+            let inserted = self.client.execute(
+                &format!(
+                    "INSERT INTO log_entries ({INSERT_COLUMNS}) SELECT {INSERT_COLUMNS} FROM incoming_entries
+                     ON CONFLICT (event_id) DO NOTHING"
+                ),
+                &[],
+            ).await?;
+            Ok(inserted)
+        })
+    }
+
+    fn list_entries(&self, limit: usize, filter: &EntryFilter) -> DbResult<Vec<LogEntry>> {
+        let mut sql = format!("SELECT {} FROM log_entries WHERE TRUE", ENTRY_COLUMNS);
+        let mut params = vec![Value::Text(self.device_id.clone())];
+
+        push_filters(&mut sql, &mut params, filter, &PgDialect);
+        params.push(Value::Int(limit as i64));
+        sql.push_str(&format!(" ORDER BY timestamp DESC LIMIT ${}", params.len()));
+
+        let rows = self.query(&sql, params)?;
+        let entries = rows.iter().map(entry_from_row).collect();
+        Ok(entries)
+    }
+
+    fn entries_matching(&self, filter: &EntryFilter) -> DbResult<Vec<LogEntry>> {
+        let mut sql = format!("SELECT {} FROM log_entries WHERE TRUE", ENTRY_COLUMNS);
+        let mut params = vec![Value::Text(self.device_id.clone())];
+
+        push_filters(&mut sql, &mut params, filter, &PgDialect);
+        sql.push_str(" ORDER BY timestamp ASC");
+
+        let rows = self.query(&sql, params)?;
+        Ok(rows.iter().map(entry_from_row).collect())
+    }
+
+    fn entries_for_file(&self, repo: &RepoScope, path: &str, commits: &[String], event_ids: &[String]) -> DbResult<Vec<(LogEntry, bool)>> {
+        let mut sql = format!(
+            "SELECT * FROM (
+                SELECT {}, EXISTS (SELECT 1 FROM entry_files f WHERE f.event_id = log_entries.event_id AND f.path = $2) AS direct
+                FROM log_entries WHERE TRUE",
+            ENTRY_COLUMNS
+        );
+        let mut params = vec![
+            Value::Text(self.device_id.clone()),
+            Value::Text(path.to_string()),
+            Value::Texts(commits.to_vec()),
+            Value::Texts(event_ids.to_vec()),
+        ];
+        push_repo_scope(&mut sql, &mut params, repo, &PgDialect);
+        sql.push_str(") e WHERE direct OR repo_commit = ANY($3) OR event_id = ANY($4) ORDER BY timestamp ASC");
+
+        let rows = self.query(&sql, params)?;
+        Ok(rows.iter().map(|r| (entry_from_row(r), r.get("direct"))).collect())
+    }
+
+    fn distinct_commits(&self, filter: &EntryFilter) -> DbResult<Vec<String>> {
+        let mut sql = String::from("SELECT DISTINCT repo_commit FROM log_entries WHERE repo_commit IS NOT NULL");
+        let mut params = vec![Value::Text(self.device_id.clone())];
+        push_filters(&mut sql, &mut params, filter, &PgDialect);
+
+        let rows = self.query(&sql, params)?;
+        Ok(rows.iter().map(|r| r.get(0)).collect())
+    }
+
+    fn remap_commits(&self, repo: &RepoScope, pairs: &[(String, String)]) -> DbResult<u64> {
+        let (old, new): (Vec<String>, Vec<String>) = pairs.iter().filter(|(o, n)| o != n).cloned().unzip();
+        if old.is_empty() {
+            return Ok(0);
+        }
+        let mut sql = String::from(
+            "UPDATE log_entries SET repo_commit_original = COALESCE(repo_commit_original, repo_commit), repo_commit = m.new
+             FROM unnest($2::text[], $3::text[]) AS m(old, new)
+             WHERE device_id = $1 AND repo_commit = m.old"
+        );
+        let mut params = vec![Value::Text(self.device_id.clone())];
+        params.push(Value::Texts(old));
+        params.push(Value::Texts(new));
+        push_repo_scope(&mut sql, &mut params, repo, &PgDialect);
+
+        let params = to_sql(params);
+        let updated = self.rt.block_on(self.client.execute(&sql, &params.iter().map(|p| &**p).collect::<Vec<_>>()))?;
+        Ok(updated)
+    }
+
+    fn list_entries_received_after(&self, after: DateTime<Utc>, filter: &EntryFilter) -> DbResult<Vec<LogEntry>> {
+        let mut sql = format!("SELECT {} FROM log_entries WHERE received_at > $2", ENTRY_COLUMNS);
+        let mut params = vec![Value::Text(self.device_id.clone())];
+        params.push(Value::Time(after));
+
+        push_filters(&mut sql, &mut params, filter, &PgDialect);
+        sql.push_str(" ORDER BY received_at ASC");
+
+        let rows = self.query(&sql, params)?;
+        let entries = rows.iter().map(entry_from_row).collect();
+        Ok(entries)
+    }
+}
+
+/// v1: ULID session ids and one active session per anchor.
+///
+/// Legacy `{ppid}_{unix_seconds}` ids are rewritten to ULIDs (stamped with the
+/// session's first_seen) together with the entries that reference them.
+/// Duplicate active sessions are retired, keeping the most recently seen,
+/// before the unique indexes are created.
+async fn migrate_v1_session_ids(tx: &tokio_postgres::Transaction<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    // Pre-anchor sessions can never be matched again
+    tx.execute(
+        "UPDATE sessions SET is_active = FALSE
+         WHERE is_active AND session_key IS NULL AND anchor_start IS NULL",
+        &[],
+    ).await?;
+    tx.execute(
+        "UPDATE sessions s SET is_active = FALSE
+         WHERE s.is_active AND s.session_key IS NULL AND EXISTS (
+             SELECT 1 FROM sessions o
+             WHERE o.is_active AND o.session_key IS NULL
+               AND o.device_id = s.device_id AND o.ppid = s.ppid AND o.anchor_start = s.anchor_start
+               AND (o.last_seen, o.session_id) > (s.last_seen, s.session_id))",
+        &[],
+    ).await?;
+    tx.execute(
+        "UPDATE sessions s SET is_active = FALSE
+         WHERE s.is_active AND s.session_key IS NOT NULL AND EXISTS (
+             SELECT 1 FROM sessions o
+             WHERE o.is_active AND o.device_id = s.device_id AND o.session_key = s.session_key
+               AND (o.last_seen, o.session_id) > (s.last_seen, s.session_id))",
+        &[],
+    ).await?;
+
+    let legacy = tx.query(
+        "SELECT session_id, device_id, first_seen FROM sessions WHERE session_id ~ '^[0-9]+_[0-9]+$'",
+        &[],
+    ).await?;
+    for row in legacy {
+        let old_id: String = row.get(0);
+        let device_id: String = row.get(1);
+        let first_seen: DateTime<Utc> = row.get(2);
+        let new_id = Ulid::from_datetime(first_seen.into()).to_string();
+        tx.execute("UPDATE sessions SET session_id = $1 WHERE session_id = $2", &[&new_id, &old_id]).await?;
+        tx.execute(
+            "UPDATE log_entries SET session_id = $1 WHERE session_id = $2 AND device_id = $3",
+            &[&new_id, &old_id, &device_id],
+        ).await?;
+    }
+
+    tx.batch_execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS uniq_sessions_active_anchor
+             ON sessions(device_id, ppid, anchor_start) WHERE is_active AND session_key IS NULL;
+         CREATE UNIQUE INDEX IF NOT EXISTS uniq_sessions_active_key
+             ON sessions(device_id, session_key) WHERE is_active AND session_key IS NOT NULL;",
+    ).await?;
+    Ok(())
+}
@@ -0,0 +1,631 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, SecondsFormat, Utc};
+use dirs::home_dir;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::ToSqlOutput;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row, ToSql, Transaction, TransactionBehavior};
+use ulid::Ulid;
+use crate::models::{EntryFilter, LogEntry, RepoScope, Session, SessionLookup};
+use super::{push_filters, push_repo_scope, DbResult, Dialect, Store, Value, ENTRY_COLUMNS};
+
+const SCHEMA_VERSION: i32 = 2;
+
+/// Same tables as the Postgres schema, minus the device registry and sync
+/// state; timestamps are RFC 3339 text in UTC with fixed-width microseconds,
+/// so they sort as strings
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS log_entries (
+    event_id TEXT PRIMARY KEY,
+    device_id TEXT NOT NULL,
+    ppid INTEGER NOT NULL,
+    name TEXT,
+    timestamp TEXT NOT NULL,
+    directory TEXT NOT NULL,
+    message TEXT NOT NULL,
+    session_id TEXT NOT NULL,
+    repo_root TEXT,
+    repo_branch TEXT,
+    repo_commit TEXT,
+    received_at TEXT NOT NULL,
+    repo_staged INTEGER,
+    repo_unstaged INTEGER,
+    repo_untracked INTEGER,
+    repo_upstream TEXT,
+    repo_ahead INTEGER,
+    repo_behind INTEGER,
+    repo_describe TEXT,
+    repo_id TEXT,
+    repo_worktree TEXT,
+    repo_superproject TEXT,
+    kind TEXT,
+    repo_commit_original TEXT,
+    vcs TEXT,
+    project_root TEXT,
+    snapshot TEXT,
+    repo_change TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_log_entries_device_timestamp ON log_entries(device_id, timestamp DESC);
+CREATE INDEX IF NOT EXISTS idx_log_entries_received ON log_entries(received_at);
+CREATE INDEX IF NOT EXISTS idx_log_entries_session ON log_entries(session_id);
+CREATE INDEX IF NOT EXISTS idx_log_entries_repo ON log_entries(repo_root, timestamp DESC);
+CREATE INDEX IF NOT EXISTS idx_log_entries_repo_id ON log_entries(repo_id, timestamp DESC);
+CREATE INDEX IF NOT EXISTS idx_log_entries_project ON log_entries(project_root, timestamp DESC);
+CREATE INDEX IF NOT EXISTS idx_log_entries_name ON log_entries(name, timestamp DESC);
+
+CREATE TABLE IF NOT EXISTS entry_files (
+    event_id TEXT NOT NULL REFERENCES log_entries(event_id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    PRIMARY KEY (event_id, path)
+);
+CREATE INDEX IF NOT EXISTS idx_entry_files_path ON entry_files(path);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    session_id TEXT PRIMARY KEY,
+    device_id TEXT NOT NULL,
+    ppid INTEGER NOT NULL,
+    name TEXT,
+    first_seen TEXT NOT NULL,
+    last_seen TEXT NOT NULL,
+    is_active INTEGER NOT NULL DEFAULT 1,
+    session_key TEXT,
+    anchor_start INTEGER,
+    auto_named INTEGER NOT NULL DEFAULT 0
+);
+CREATE UNIQUE INDEX IF NOT EXISTS uniq_sessions_active_anchor
+    ON sessions(device_id, ppid, anchor_start) WHERE is_active AND session_key IS NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS uniq_sessions_active_key
+    ON sessions(device_id, session_key) WHERE is_active AND session_key IS NOT NULL;
+"#;
+
+/// Upgrades for databases created by an older `SCHEMA`, oldest first; the
+/// first one upgrades version 1
+const MIGRATIONS: [&str; 1] = [
+    // jj entries stored the change id in repo_commit; the git commit wasn't recorded
+    "ALTER TABLE log_entries ADD COLUMN repo_change TEXT;
+     UPDATE log_entries SET repo_change = repo_commit, repo_commit = NULL WHERE vcs = 'jj';",
+];
+
+/// `~/.clog/clog.db`, used when no database URL is configured
+pub fn default_path() -> PathBuf {
+    home_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join(".clog")
+        .join("clog.db")
+}
+
+fn ts(t: &DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn time(r: &Row, i: usize) -> rusqlite::Result<DateTime<Utc>> {
+    let s: String = r.get(i)?;
+    DateTime::parse_from_rfc3339(&s)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(i, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+fn entry_from_row(r: &Row) -> rusqlite::Result<LogEntry> {
+    let count = |i: usize| r.get::<_, Option<i64>>(i).map(|n| n.map(|n| n as u32));
+    Ok(LogEntry {
+        ppid: r.get::<_, i64>(0)? as u32,
+        name: r.get(1)?,
+        timestamp: time(r, 2)?,
+        directory: r.get(3)?,
+        message: r.get(4)?,
+        session_id: r.get(5)?,
+        repo_root: r.get(6)?,
+        repo_branch: r.get(7)?,
+        repo_commit: r.get(8)?,
+        event_id: r.get(9)?,
+        received_at: Some(time(r, 10)?),
+        repo_staged: count(11)?,
+        repo_unstaged: count(12)?,
+        repo_untracked: count(13)?,
+        repo_upstream: r.get(14)?,
+        repo_ahead: count(15)?,
+        repo_behind: count(16)?,
+        repo_describe: r.get(17)?,
+        repo_id: r.get(18)?,
+        repo_worktree: r.get(19)?,
+        repo_superproject: r.get(20)?,
+        kind: r.get(21)?,
+        repo_commit_original: r.get(22)?,
+        project_root: r.get(23)?,
+        vcs: r.get(24)?,
+        snapshot: r.get(25)?,
+        repo_change: r.get(26)?,
+    })
+}
+
+fn session_from_row(r: &Row) -> rusqlite::Result<Session> {
+    Ok(Session {
+        session_id: r.get(0)?,
+        name: r.get(1)?,
+    })
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Value::Text(s) => ToSqlOutput::from(s.as_str()),
+            // Arrays travel as JSON and are unpacked with json_each
+            Value::Texts(list) => ToSqlOutput::from(serde_json::to_string(list).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?),
+            Value::Time(t) => ToSqlOutput::from(ts(t)),
+            Value::Int(n) => ToSqlOutput::from(*n),
+        })
+    }
+}
+
+struct SqliteDialect;
+
+impl Dialect for SqliteDialect {
+    fn param(&self, n: usize) -> String {
+        format!("?{}", n)
+    }
+
+    fn any(&self, column: &str, n: usize) -> String {
+        format!("{} IN (SELECT value FROM json_each(?{}))", column, n)
+    }
+
+    fn regex(&self, column: &str, n: usize) -> String {
+        format!("{} REGEXP ?{}", column, n)
+    }
+
+    fn today(&self, column: &str) -> String {
+        format!("date({}, 'localtime') = date('now', 'localtime')", column)
+    }
+}
+
+pub struct SqliteStore {
+    conn: Connection,
+    path: PathBuf,
+    device_id: String,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> DbResult<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        // Concurrent clog processes wait for each other instead of failing
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        conn.create_scalar_function("regexp", 2, FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
+            let re = ctx.get_or_create_aux(0, |v| regex::Regex::new(v.as_str()?).map_err(|e| rusqlite::Error::UserFunctionError(Box::new(e))))?;
+            let text: Option<String> = ctx.get(1)?;
+            Ok(text.is_some_and(|t| re.is_match(&t)))
+        })?;
+
+        let store = SqliteStore {
+            conn,
+            path: path.to_path_buf(),
+            device_id: crate::device::get_or_create_device_id()?,
+        };
+        store.ensure_schema()?;
+        Ok(store)
+    }
+
+    fn ensure_schema(&self) -> DbResult<()> {
+        let version: i32 = self.conn.query_row("PRAGMA user_version", [], |r| r.get(0))?;
+        if version < SCHEMA_VERSION {
+            let tx = self.write_transaction()?;
+            if version == 0 {
+                tx.execute_batch(SCHEMA)?;
+            } else {
+                for migration in &MIGRATIONS[version as usize - 1..] {
+                    tx.execute_batch(migration)?;
+                }
+            }
+            tx.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Transactions take the write lock up front: a read upgraded to a write
+    /// later fails with SQLITE_BUSY when another process wrote in between
+    fn write_transaction(&self) -> rusqlite::Result<Transaction<'_>> {
+        Transaction::new_unchecked(&self.conn, TransactionBehavior::Immediate)
+    }
+
+    fn insert_entry(&self, event_id: &str, entry: &LogEntry, on_conflict: &str) -> DbResult<u64> {
+        let count = |n: Option<u32>| n.map(|n| n as i64);
+        let sql = format!(
+            "INSERT INTO log_entries (
+                event_id, device_id, ppid, name, timestamp, directory, message, session_id,
+                repo_root, repo_branch, repo_commit,
+                repo_staged, repo_unstaged, repo_untracked, repo_upstream, repo_ahead, repo_behind, repo_describe,
+                repo_id, repo_worktree, repo_superproject, kind, project_root, vcs, snapshot, repo_change, received_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27){}",
+            on_conflict
+        );
+        let inserted = self.conn.execute(
+            &sql,
+            params![
+                event_id,
+                self.device_id,
+                entry.ppid as i64,
+                entry.name,
+                ts(&entry.timestamp),
+                entry.directory,
+                entry.message,
+                entry.session_id,
+                entry.repo_root,
+                entry.repo_branch,
+                entry.repo_commit,
+                count(entry.repo_staged),
+                count(entry.repo_unstaged),
+                count(entry.repo_untracked),
+                entry.repo_upstream,
+                count(entry.repo_ahead),
+                count(entry.repo_behind),
+                entry.repo_describe,
+                entry.repo_id,
+                entry.repo_worktree,
+                entry.repo_superproject,
+                entry.kind,
+                entry.project_root,
+                entry.vcs,
+                entry.snapshot,
+                entry.repo_change,
+                ts(&Utc::now()),
+            ],
+        )?;
+        Ok(inserted as u64)
+    }
+
+    fn query_entries(&self, sql: &str, params: Vec<Value>) -> DbResult<Vec<LogEntry>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let entries = stmt.query_map(params_from_iter(params.iter()), entry_from_row)?.collect::<Result<_, _>>()?;
+        Ok(entries)
+    }
+}
+
+impl Store for SqliteStore {
+    fn describe(&self) -> String {
+        format!("SQLite {}", self.path.display())
+    }
+
+    fn get_active_session(&self, ppid: u32, start_time: u64) -> DbResult<Option<Session>> {
+        let session = self.conn.query_row(
+            "SELECT session_id, name
+             FROM sessions
+             WHERE device_id = ?1 AND ppid = ?2 AND anchor_start = ?3 AND is_active
+             ORDER BY last_seen DESC
+             LIMIT 1",
+            params![self.device_id, ppid as i64, start_time as i64],
+            session_from_row,
+        ).optional()?;
+        Ok(session)
+    }
+
+    fn get_session_by_key(&self, key: &str) -> DbResult<Option<Session>> {
+        let session = self.conn.query_row(
+            "SELECT session_id, name
+             FROM sessions
+             WHERE device_id = ?1 AND session_key = ?2 AND is_active
+             ORDER BY last_seen DESC
+             LIMIT 1",
+            params![self.device_id, key],
+            session_from_row,
+        ).optional()?;
+        Ok(session)
+    }
+
+    fn create_keyed_session(&self, ppid: u32, key: &str, name: &str) -> DbResult<String> {
+        let session_id = self.conn.query_row(
+            "INSERT INTO sessions (session_id, device_id, ppid, name, session_key, first_seen, last_seen, is_active)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6, 1)
+             ON CONFLICT (device_id, session_key) WHERE is_active AND session_key IS NOT NULL
+             DO UPDATE SET last_seen = excluded.last_seen
+             RETURNING session_id",
+            params![Ulid::new().to_string(), self.device_id, ppid as i64, name, key, ts(&Utc::now())],
+            |r| r.get(0),
+        )?;
+        Ok(session_id)
+    }
+
+    fn create_session(&self, ppid: u32, start_time: u64, name: Option<&str>, auto_named: bool) -> DbResult<String> {
+        let tx = self.write_transaction()?;
+        // Any other active session on this PID belonged to an earlier process
+        tx.execute(
+            "UPDATE sessions SET is_active = 0
+             WHERE device_id = ?1 AND ppid = ?2 AND session_key IS NULL
+               AND anchor_start IS NOT ?3 AND is_active",
+            params![self.device_id, ppid as i64, start_time as i64],
+        )?;
+        let session_id: String = tx.query_row(
+            "INSERT INTO sessions (session_id, device_id, ppid, anchor_start, name, auto_named, first_seen, last_seen, is_active)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7, 1)
+             ON CONFLICT (device_id, ppid, anchor_start) WHERE is_active AND session_key IS NULL
+             DO UPDATE SET last_seen = excluded.last_seen,
+                           name = COALESCE(excluded.name, sessions.name),
+                           auto_named = CASE WHEN excluded.name IS NULL THEN sessions.auto_named ELSE excluded.auto_named END
+             RETURNING session_id",
+            params![Ulid::new().to_string(), self.device_id, ppid as i64, start_time as i64, name, auto_named, ts(&Utc::now())],
+            |r| r.get(0),
+        )?;
+        tx.commit()?;
+        Ok(session_id)
+    }
+
+    fn update_session_name(&self, session_id: &str, name: &str) -> DbResult<u64> {
+        let tx = self.write_transaction()?;
+        let prev: Option<(Option<String>, bool)> = tx.query_row(
+            "SELECT name, auto_named FROM sessions WHERE session_id = ?1 AND device_id = ?2",
+            params![session_id, self.device_id],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        ).optional()?;
+        tx.execute(
+            "UPDATE sessions SET name = ?1, last_seen = ?2, auto_named = 0 WHERE session_id = ?3 AND device_id = ?4",
+            params![name, ts(&Utc::now()), session_id, self.device_id],
+        )?;
+        let relabeled = match prev {
+            Some((prev_name, true)) => tx.execute(
+                "UPDATE log_entries SET name = ?1 WHERE session_id = ?2 AND device_id = ?3 AND name IS ?4",
+                params![name, session_id, self.device_id, prev_name],
+            )?,
+            _ => 0,
+        };
+        tx.commit()?;
+        Ok(relabeled as u64)
+    }
+
+    fn update_session_last_seen(&self, session_id: &str) -> DbResult<()> {
+        self.conn.execute(
+            "UPDATE sessions SET last_seen = ?1 WHERE session_id = ?2 AND device_id = ?3",
+            params![ts(&Utc::now()), session_id, self.device_id],
+        )?;
+        Ok(())
+    }
+
+    fn insert_log_entry(&self, entry: &LogEntry) -> DbResult<String> {
+        let event_id = entry.event_id.clone().unwrap_or_else(|| Ulid::new().to_string());
+        self.insert_entry(&event_id, entry, " ON CONFLICT (event_id) DO NOTHING")?;
+        Ok(event_id)
+    }
+
+    fn insert_into_session(&self, lookup: &SessionLookup, entry: &LogEntry, files: &[String]) -> DbResult<Option<LogEntry>> {
+        let tx = self.write_transaction()?;
+        let session: Option<(String, Option<String>)> = match lookup {
+            SessionLookup::Anchor { ppid, start_time } => tx.query_row(
+                "SELECT session_id, name FROM sessions
+                 WHERE device_id = ?1 AND ppid = ?2 AND anchor_start = ?3 AND is_active
+                 ORDER BY last_seen DESC LIMIT 1",
+                params![self.device_id, *ppid as i64, *start_time as i64],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            ),
+            SessionLookup::Key { key, name } => tx.query_row(
+                "SELECT session_id, name FROM sessions
+                 WHERE device_id = ?1 AND session_key = ?2 AND (?3 IS NULL OR name = ?3) AND is_active
+                 ORDER BY last_seen DESC LIMIT 1",
+                params![self.device_id, key, name],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            ),
+        }.optional()?;
+        let Some((session_id, name)) = session else { return Ok(None) };
+
+        let entry = LogEntry { session_id, name, ..entry.clone() };
+        let event_id = entry.event_id.clone().unwrap_or_else(|| Ulid::new().to_string());
+        // Already stored (a retried write): leave the session and files alone
+        if self.insert_entry(&event_id, &entry, " ON CONFLICT (event_id) DO NOTHING")? == 0 {
+            return Ok(None);
+        }
+        self.update_session_last_seen(&entry.session_id)?;
+        self.insert_entry_files(&event_id, files)?;
+        tx.commit()?;
+        Ok(Some(LogEntry { event_id: Some(event_id), ..entry }))
+    }
+
+    fn insert_entry_files(&self, event_id: &str, paths: &[String]) -> DbResult<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.conn.execute(
+            "INSERT INTO entry_files (event_id, path) SELECT ?1, value FROM json_each(?2) WHERE true ON CONFLICT DO NOTHING",
+            params![event_id, Value::Texts(paths.to_vec())],
+        )?;
+        Ok(())
+    }
+
+    fn insert_log_entries(&self, entries: &[LogEntry]) -> DbResult<u64> {
+        let tx = self.write_transaction()?;
+        let mut inserted = 0;
+        for entry in entries {
+            let event_id = entry.event_id.clone().unwrap_or_else(|| Ulid::new().to_string());
+            inserted += self.insert_entry(&event_id, entry, " ON CONFLICT (event_id) DO NOTHING")?;
+        }
+        tx.commit()?;
+        Ok(inserted)
+    }
+
+    fn list_entries(&self, limit: usize, filter: &EntryFilter) -> DbResult<Vec<LogEntry>> {
+        let mut sql = format!("SELECT {} FROM log_entries WHERE TRUE", ENTRY_COLUMNS);
+        let mut params = vec![Value::Text(self.device_id.clone())];
+
+        push_filters(&mut sql, &mut params, filter, &SqliteDialect);
+        params.push(Value::Int(limit as i64));
+        sql.push_str(&format!(" ORDER BY timestamp DESC LIMIT ?{}", params.len()));
+
+        self.query_entries(&sql, params)
+    }
+
+    fn entries_matching(&self, filter: &EntryFilter) -> DbResult<Vec<LogEntry>> {
+        let mut sql = format!("SELECT {} FROM log_entries WHERE TRUE", ENTRY_COLUMNS);
+        let mut params = vec![Value::Text(self.device_id.clone())];
+
+        push_filters(&mut sql, &mut params, filter, &SqliteDialect);
+        sql.push_str(" ORDER BY timestamp ASC");
+
+        self.query_entries(&sql, params)
+    }
+
+    fn entries_for_file(&self, repo: &RepoScope, path: &str, commits: &[String], event_ids: &[String]) -> DbResult<Vec<(LogEntry, bool)>> {
+        let mut sql = format!(
+            "SELECT * FROM (
+                SELECT {}, EXISTS (SELECT 1 FROM entry_files f WHERE f.event_id = log_entries.event_id AND f.path = ?2) AS direct
+                FROM log_entries WHERE TRUE",
+            ENTRY_COLUMNS
+        );
+        let mut params = vec![
+            Value::Text(self.device_id.clone()),
+            Value::Text(path.to_string()),
+            Value::Texts(commits.to_vec()),
+            Value::Texts(event_ids.to_vec()),
+        ];
+        push_repo_scope(&mut sql, &mut params, repo, &SqliteDialect);
+        sql.push_str(&format!(
+            ") e WHERE direct OR {} OR {} ORDER BY timestamp ASC",
+            SqliteDialect.any("repo_commit", 3),
+            SqliteDialect.any("event_id", 4)
+        ));
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt
+            .query_map(params_from_iter(params.iter()), |r| Ok((entry_from_row(r)?, r.get("direct")?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(rows)
+    }
+
+    fn distinct_commits(&self, filter: &EntryFilter) -> DbResult<Vec<String>> {
+        let mut sql = String::from("SELECT DISTINCT repo_commit FROM log_entries WHERE repo_commit IS NOT NULL");
+        let mut params = vec![Value::Text(self.device_id.clone())];
+        push_filters(&mut sql, &mut params, filter, &SqliteDialect);
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let commits = stmt.query_map(params_from_iter(params.iter()), |r| r.get(0))?.collect::<Result<_, _>>()?;
+        Ok(commits)
+    }
+
+    fn remap_commits(&self, repo: &RepoScope, pairs: &[(String, String)]) -> DbResult<u64> {
+        let pairs: Vec<&(String, String)> = pairs.iter().filter(|(o, n)| o != n).collect();
+        if pairs.is_empty() {
+            return Ok(0);
+        }
+        let mut sql = String::from(
+            "UPDATE log_entries SET repo_commit_original = COALESCE(repo_commit_original, repo_commit), repo_commit = m.new_sha
+             FROM (SELECT value ->> 0 AS old_sha, value ->> 1 AS new_sha FROM json_each(?2)) AS m
+             WHERE device_id = ?1 AND repo_commit = m.old_sha"
+        );
+        let mut params = vec![Value::Text(self.device_id.clone()), Value::Text(serde_json::to_string(&pairs)?)];
+        push_repo_scope(&mut sql, &mut params, repo, &SqliteDialect);
+
+        let updated = self.conn.execute(&sql, params_from_iter(params.iter()))?;
+        Ok(updated as u64)
+    }
+
+    fn list_entries_received_after(&self, after: DateTime<Utc>, filter: &EntryFilter) -> DbResult<Vec<LogEntry>> {
+        let mut sql = format!("SELECT {} FROM log_entries WHERE received_at > ?2", ENTRY_COLUMNS);
+        let mut params = vec![Value::Text(self.device_id.clone()), Value::Time(after)];
+
+        push_filters(&mut sql, &mut params, filter, &SqliteDialect);
+        sql.push_str(" ORDER BY received_at ASC");
+
+        self.query_entries(&sql, params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::RepoScope;
+
+    fn entry(message: &str, commit: &str) -> LogEntry {
+        LogEntry {
+            ppid: 1,
+            name: Some("test".to_string()),
+            timestamp: Utc::now(),
+            directory: "/repo".to_string(),
+            message: message.to_string(),
+            session_id: "s".to_string(),
+            vcs: Some("git".to_string()),
+            repo_root: Some("/repo".to_string()),
+            repo_branch: Some("main".to_string()),
+            repo_commit: Some(commit.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn entries_sessions_and_filters() {
+        let path = std::env::temp_dir().join(format!("clog-test-{}.db", Ulid::new()));
+        let db = SqliteStore::open(&path).unwrap();
+
+        let sid = db.create_session(42, 1000, Some("auto"), true).unwrap();
+        assert_eq!(db.create_session(42, 1000, None, false).unwrap(), sid);
+        assert_eq!(db.get_active_session(42, 1000).unwrap().unwrap().session_id, sid);
+        // A new process on the same PID retires the old session
+        assert_ne!(db.create_session(42, 2000, None, false).unwrap(), sid);
+        assert!(db.get_active_session(42, 1000).unwrap().is_none());
+        let keyed = db.create_keyed_session(7, "k", "k").unwrap();
+        assert_eq!(db.create_keyed_session(8, "k", "k").unwrap(), keyed);
+
+        let mut first = entry("first", "aaa");
+        first.session_id = sid.clone();
+        first.name = Some("auto".to_string());
+        let id = db.insert_log_entry(&first).unwrap();
+        db.insert_entry_files(&id, &["src/db.rs".to_string()]).unwrap();
+        db.insert_log_entry(&entry("second", "bbb")).unwrap();
+        assert_eq!(db.update_session_name(&sid, "named").unwrap(), 1);
+
+        let all = db.list_entries(10, &EntryFilter::default()).unwrap();
+        assert_eq!(all.iter().map(|e| e.message.as_str()).collect::<Vec<_>>(), ["second", "first"]);
+        assert_eq!(all[1].name.as_deref(), Some("named"));
+
+        let by_path = EntryFilter { path: Some("*.rs".to_string()), ..Default::default() };
+        assert_eq!(db.entries_matching(&by_path).unwrap().len(), 1);
+        let by_commits = EntryFilter { commits: Some(vec!["bbb".to_string()]), ..Default::default() };
+        assert_eq!(db.entries_matching(&by_commits).unwrap()[0].message, "second");
+
+        let repo = RepoScope { id: None, root: Some("/repo".to_string()), worktrees: vec!["/repo-wt".to_string()] };
+        assert_eq!(db.remap_commits(&repo, &[("aaa".to_string(), "ccc".to_string())]).unwrap(), 1);
+        let blamed = db.entries_for_file(&repo, "src/db.rs", &[], &[]).unwrap();
+        assert_eq!(blamed.len(), 1);
+        assert!(blamed[0].1);
+        assert_eq!(blamed[0].0.repo_commit.as_deref(), Some("ccc"));
+        assert_eq!(blamed[0].0.repo_commit_original.as_deref(), Some("aaa"));
+
+        let imported = LogEntry { event_id: Some(id.clone()), ..entry("dup", "ddd") };
+        let retried = LogEntry { event_id: Some(id.clone()), ..entry("again", "aaa") };
+        assert_eq!(db.insert_log_entry(&retried).unwrap(), id);
+        let batch = [imported, entry("third", "eee"), entry("fourth", "eee")];
+        assert_eq!(db.insert_log_entries(&batch).unwrap(), 2);
+        assert_eq!(db.list_entries(1, &EntryFilter::default()).unwrap()[0].message, "fourth");
+
+        // A clone on another device shares the repo id, not the path
+        db.conn.execute(
+            "UPDATE log_entries SET device_id = 'laptop', repo_id = 'github.com/org/app', repo_root = '/elsewhere/app' WHERE message = 'third'",
+            [],
+        ).unwrap();
+        let messages = |filter: EntryFilter| -> Vec<String> {
+            db.entries_matching(&filter).unwrap().into_iter().map(|e| e.message).collect()
+        };
+        let by_id = RepoScope { id: Some("github.com/org/app".to_string()), root: Some("/repo".to_string()), worktrees: Vec::new() };
+        assert!(messages(EntryFilter { repo: Some(by_id), ..Default::default() }).contains(&"third".to_string()));
+        assert!(!messages(EntryFilter { repo: Some(repo.clone()), ..Default::default() }).contains(&"third".to_string()));
+        assert!(!messages(EntryFilter::default()).contains(&"third".to_string()));
+
+        // Logged in a linked worktree before worktrees were recorded separately
+        db.conn.execute("UPDATE log_entries SET repo_root = '/repo-wt' WHERE message = 'fourth'", []).unwrap();
+        assert!(messages(EntryFilter { repo: Some(repo.clone()), ..Default::default() }).contains(&"fourth".to_string()));
+        let without_worktrees = RepoScope { worktrees: Vec::new(), ..repo };
+        assert!(!messages(EntryFilter { repo: Some(without_worktrees), ..Default::default() }).contains(&"fourth".to_string()));
+
+        // A retried write finds its event id stored and changes nothing
+        let lookup = SessionLookup::Key { key: "k".to_string(), name: None };
+        let retry = LogEntry { event_id: Some("01RETRY".to_string()), ..entry("retry", "fff") };
+        let stored = db.insert_into_session(&lookup, &retry, &["a.rs".to_string()]).unwrap().unwrap();
+        assert_eq!((stored.session_id.as_str(), stored.event_id.as_deref()), (keyed.as_str(), Some("01RETRY")));
+        assert!(db.insert_into_session(&lookup, &retry, &["b.rs".to_string()]).unwrap().is_none());
+        let files: i64 = db.conn.query_row("SELECT COUNT(*) FROM entry_files WHERE event_id = '01RETRY'", [], |r| r.get(0)).unwrap();
+        assert_eq!(files, 1);
+
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
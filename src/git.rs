@@ -1,22 +1,679 @@
-use std::path::Path;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 use std::process::Command;
-use crate::models::RepoInfo;
+use chrono::{DateTime, Utc};
+use crate::commit_msg::TRAILER_KEY;
+use crate::models::{Commit, FileCommit, RepoInfo, RepoScope};
+
+/// Root commit cache in the common git directory (see `root_commit`)
+const REPO_ID_CACHE: &str = "clog-repo-id";
+
+/// Full repository context for a new entry.
+///
+/// Discovery (root, branch, commit, upstream) reads `.git` directly unless
+/// `use_cli` is set and the `git-cli` feature is compiled in. Working-tree
+/// counts, ahead/behind, changed paths and describe still spawn `git status`
+/// and `git describe` (in parallel, about 2 ms together on a small repo) and
+/// are left empty when `git` isn't on PATH.
+pub fn detect_repo_info(cwd: &Path, use_cli: bool) -> Option<RepoInfo> {
+    // Outside a repository, don't spawn anything
+    let discovered = if cli(use_cli) { None } else { Some(discover(cwd)?) };
+
+    // The git processes are independent; run them side by side
+    let (status, describe, id) = std::thread::scope(|s| {
+        let describe = s.spawn(|| run_git_command(cwd, &["describe", "--tags"]));
+        let id = s.spawn(|| repo_id(cwd, use_cli));
+        let status = run_git_command(cwd, &["status", "--porcelain=v2", "--branch", "-z"]);
+        (status, describe.join().ok().flatten(), id.join().ok().flatten())
+    });
+    let status = status.and_then(|s| parse_status(&s));
+
+    let mut info = match discovered {
+        Some(mut info) => {
+            if let Some(status) = status {
+                info.staged = status.staged;
+                info.unstaged = status.unstaged;
+                info.untracked = status.untracked;
+                info.ahead = status.ahead;
+                info.behind = status.behind;
+                info.files = status.files;
+            }
+            info
+        }
+        None => {
+            let mut info = status?;
+            let layout = layout_cli(cwd)?;
+            info.root = layout.root;
+            info.worktree = layout.worktree;
+            info.superproject = layout.superproject;
+            info
+        }
+    };
+
+    info.describe = describe
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty());
+    info.id = id;
+    Some(info)
+}
+
+/// Root and identity only; cheaper than full detection when listing.
+/// Linked worktrees scope to their main repository.
+pub fn detect_repo_scope(cwd: &Path, use_cli: bool) -> Option<RepoScope> {
+    let layout = detect_layout(cwd, use_cli)?;
+    let worktrees = git_dirs(cwd).map(|(_, _, common)| linked_worktrees(&common)).unwrap_or_default();
+    Some(RepoScope { id: repo_id(cwd, use_cli), root: Some(layout.root), worktrees })
+}
+
+/// Top levels of the linked worktrees registered in a common git directory
+fn linked_worktrees(common: &Path) -> Vec<String> {
+    let Ok(dirs) = fs::read_dir(common.join("worktrees")) else { return Vec::new() };
+    let mut worktrees: Vec<String> = dirs
+        .flatten()
+        // `gitdir` holds the path of the worktree's `.git` file
+        .filter_map(|d| fs::read_to_string(d.path().join("gitdir")).ok())
+        .filter_map(|gitdir| Path::new(gitdir.trim()).parent().map(|p| display(&p.canonicalize().unwrap_or_else(|_| p.to_path_buf()))))
+        .collect();
+    worktrees.sort();
+    worktrees
+}
+
+/// Where a checkout sits within its repository
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    /// Main checkout (for linked worktrees, the one owning the git directory)
+    pub root: String,
+    /// Top level of the current worktree
+    pub worktree: String,
+    /// Working tree of the superproject when this is a submodule
+    pub superproject: Option<String>,
+}
+
+pub fn detect_layout(cwd: &Path, use_cli: bool) -> Option<Layout> {
+    if cli(use_cli) { layout_cli(cwd) } else { layout_native(cwd) }
+}
+
+/// Whether discovery shells out to `git rev-parse`: `git.use_cli` in builds
+/// with the `git-cli` feature, never otherwise. Tests always keep it, so the
+/// native-vs-CLI comparisons run in every configuration.
+fn cli(use_cli: bool) -> bool {
+    use_cli && cfg!(any(test, feature = "git-cli"))
+}
+
+fn layout_native(cwd: &Path) -> Option<Layout> {
+    let (toplevel, git_dir) = find_git_dir(&cwd.canonicalize().ok()?)?;
+    let git_dir = git_dir.canonicalize().ok()?;
+    let common = common_dir(&git_dir).canonicalize().ok()?;
+
+    // A submodule's git dir lives at `<super git dir>/modules/<name>`
+    let superproject = git_dir
+        .ancestors()
+        .skip(1)
+        .find(|a| a.file_name().is_some_and(|n| n == "modules") && a.parent().is_some_and(|p| p.join("HEAD").is_file()))
+        .and_then(|modules| modules.parent())
+        .and_then(work_tree_of);
+
+    Some(Layout {
+        root: display(&work_tree_of(&common).unwrap_or_else(|| toplevel.clone())),
+        worktree: display(&toplevel),
+        superproject: superproject.map(|p| display(&p)),
+    })
+}
+
+fn layout_cli(cwd: &Path) -> Option<Layout> {
+    let toplevel = run_git_command(cwd, &["rev-parse", "--show-toplevel"])?.trim().to_string();
+    let common = run_git_command(cwd, &["rev-parse", "--git-common-dir"])?;
+    let common = cwd.join(common.trim()).canonicalize().ok()?;
+    let superproject = run_git_command(cwd, &["rev-parse", "--show-superproject-working-tree"])
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+
+    Some(Layout {
+        root: work_tree_of(&common).map(|p| display(&p)).unwrap_or_else(|| toplevel.clone()),
+        worktree: toplevel,
+        superproject,
+    })
+}
+
+/// Work tree owning a git directory: the parent of a `.git` dir, or
+/// `core.worktree` for absorbed submodule git dirs
+fn work_tree_of(git_dir: &Path) -> Option<PathBuf> {
+    if git_dir.file_name().is_some_and(|n| n == ".git") {
+        return git_dir.parent().map(Path::to_path_buf);
+    }
+    config_value(git_dir, "core", None, "worktree")
+        .and_then(|wt| git_dir.join(wt).canonicalize().ok())
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+/// Identity of the repository that survives clones, paths and machines: the
+/// normalized `origin` URL, or `root:<sha>` of the root commit without one
+pub fn repo_id(cwd: &Path, use_cli: bool) -> Option<String> {
+    let common = if cli(use_cli) {
+        let dir = run_git_command(cwd, &["rev-parse", "--git-common-dir"])?;
+        cwd.join(dir.trim())
+    } else {
+        let (_, git_dir) = find_git_dir(&cwd.canonicalize().ok()?)?;
+        common_dir(&git_dir)
+    };
+    let origin = if cli(use_cli) {
+        run_git_command(cwd, &["config", "--get", "remote.origin.url"]).map(|u| u.trim().to_string())
+    } else {
+        config_value(&common, "remote", Some("origin"), "url")
+    };
+    if let Some(url) = origin.filter(|u| !u.is_empty()) {
+        return Some(normalize_remote_url(&url));
+    }
+    root_commit(cwd, &common).map(|sha| format!("root:{}", sha))
+}
+
+/// Root commit of HEAD's history. Finding it walks the whole history, so the
+/// answer is kept in `<common git dir>/clog-repo-id`; delete it to recompute.
+fn root_commit(cwd: &Path, common: &Path) -> Option<String> {
+    let cache = common.join(REPO_ID_CACHE);
+    let cached = fs::read_to_string(&cache).ok().map(|s| s.trim().to_string());
+    if let Some(sha) = cached.filter(|s| s.len() >= 40 && s.chars().all(|c| c.is_ascii_hexdigit())) {
+        return Some(sha);
+    }
+    // Several roots are possible after merging unrelated histories; pick one stably
+    let sha = run_git_command(cwd, &["rev-list", "--max-parents=0", "HEAD"])?
+        .lines()
+        .min()?
+        .to_string();
+    // Best effort: a read-only repository just walks again next time
+    let tmp = cache.with_extension(format!("tmp-{}", std::process::id()));
+    if fs::write(&tmp, format!("{}\n", sha)).is_ok() && fs::rename(&tmp, &cache).is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    Some(sha)
+}
+
+/// The subset of `commits` that are ancestors of (or equal to) HEAD.
+/// Needs the commit graph, so this always runs `git rev-list`.
+pub fn reachable_from_head(cwd: &Path, commits: &[String]) -> Option<Vec<String>> {
+    let history = run_git_command(cwd, &["rev-list", "HEAD"])?;
+    let history: HashSet<&str> = history.lines().collect();
+    Some(commits.iter().filter(|c| history.contains(c.as_str())).cloned().collect())
+}
+
+/// Commit id of HEAD, or None outside a repository or before the first commit
+pub fn head_commit(cwd: &Path, use_cli: bool) -> Option<String> {
+    if cli(use_cli) {
+        return run_git_command(cwd, &["rev-parse", "--verify", "-q", "HEAD"]).map(|s| s.trim().to_string());
+    }
+    let (_, git_dir) = find_git_dir(&cwd.canonicalize().ok()?)?;
+    resolve_ref(&git_dir, &common_dir(&git_dir), "HEAD")
+}
+
+/// Directory git runs hooks from, honoring `core.hooksPath`. Linked worktrees
+/// share the main repository's hooks.
+pub fn hooks_dir(cwd: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = run_git_checked(cwd, &["rev-parse", "--git-path", "hooks"])?;
+    Ok(cwd.join(path.trim()))
+}
+
+/// Add (or replace) a trailer in a commit message file with `git interpret-trailers`
+pub fn set_trailer(cwd: &Path, file: &Path, trailer: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let file = file.to_string_lossy();
+    run_git_checked(cwd, &["interpret-trailers", "--in-place", "--if-exists", "replace", "--trailer", trailer, &file])?;
+    Ok(())
+}
+
+/// Whether a rebase is under way in this worktree
+pub fn rebase_in_progress(cwd: &Path) -> bool {
+    let Some((_, git_dir)) = cwd.canonicalize().ok().and_then(|cwd| find_git_dir(&cwd)) else { return false };
+    git_dir.join("rebase-merge").is_dir() || git_dir.join("rebase-apply").is_dir()
+}
+
+/// Short name of the checked-out branch; None when detached
+pub fn current_branch(cwd: &Path) -> Option<String> {
+    run_git_command(cwd, &["symbolic-ref", "--short", "-q", "HEAD"]).map(|s| s.trim().to_string())
+}
+
+/// Commits selected by a revision range (`v1.2..v1.3`, `main~5..`, ...),
+/// newest first
+pub fn commits_in_range(cwd: &Path, range: &str) -> Result<Vec<Commit>, Box<dyn std::error::Error>> {
+    let out = run_git_checked(cwd, &["log", COMMIT_FORMAT, range, "--"])?;
+    Ok(out.lines().filter_map(parse_commit_line).collect())
+}
+
+/// Commits on local branches (and a detached HEAD) since a point in time,
+/// newest first
+pub fn commits_since(cwd: &Path, since: DateTime<Utc>) -> Result<Vec<Commit>, Box<dyn std::error::Error>> {
+    let since = format!("--since={}", since.to_rfc3339());
+    let out = run_git_checked(cwd, &["log", "--branches", "HEAD", &since, COMMIT_FORMAT, "--"])?;
+    Ok(out.lines().filter_map(parse_commit_line).collect())
+}
+
+/// A single commit by revision
+pub fn commit_info(cwd: &Path, rev: &str) -> Option<Commit> {
+    let out = run_git_command(cwd, &["log", "-1", COMMIT_FORMAT, rev, "--"])?;
+    out.lines().next().and_then(parse_commit_line)
+}
+
+/// Path of `file` relative to the top of the worktree at `cwd`, resolved
+/// lexically so deleted files work too
+pub fn worktree_path(cwd: &Path, file: &Path) -> Option<String> {
+    let top = PathBuf::from(run_git_command(cwd, &["rev-parse", "--show-toplevel"])?.trim());
+    let prefix = run_git_command(cwd, &["rev-parse", "--show-prefix"])?;
+    let joined = if file.is_absolute() {
+        file.strip_prefix(&top).ok()?.to_path_buf()
+    } else {
+        Path::new(prefix.trim()).join(file)
+    };
+    let mut parts: Vec<&str> = Vec::new();
+    for component in joined.components() {
+        match component {
+            Component::Normal(c) => parts.push(c.to_str()?),
+            Component::ParentDir => { parts.pop()?; }
+            _ => {}
+        }
+    }
+    Some(parts.join("/")).filter(|p| !p.is_empty())
+}
+
+/// Commits that touched `path` (relative to the worktree top), following
+/// renames, newest first
+pub fn file_commits(cwd: &Path, path: &str) -> Result<Vec<FileCommit>, Box<dyn std::error::Error>> {
+    let format = format!("--format=%P%x1f%(trailers:key={},valueonly,separator=%x2C)%x1f%H%x1f%h%x1f%aI%x1f%s", TRAILER_KEY);
+    let pathspec = format!(":(top,literal){}", path);
+    let out = run_git_checked(cwd, &["log", "--follow", &format, "--", &pathspec])?;
+    Ok(out
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\x1f');
+            let parents = fields.next()?;
+            let entries = fields.next()?;
+            Some(FileCommit {
+                parent: parents.split_whitespace().next().map(str::to_string),
+                entries: entries.split(',').map(str::trim).filter(|e| !e.is_empty()).map(str::to_string).collect(),
+                commit: parse_commit_line(fields.next()?)?,
+            })
+        })
+        .collect())
+}
+
+/// Staged and unstaged changes to tracked files against HEAD, as a patch
+/// `git apply` takes back (binary files included)
+pub fn diff_head(cwd: &Path) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    run_git_raw(cwd, &[
+        "diff", "HEAD", "--binary", "--no-ext-diff", "--no-textconv", "--no-color",
+        "--src-prefix=a/", "--dst-prefix=b/",
+    ], b"")
+}
+
+/// No staged or unstaged changes to tracked files
+pub fn is_clean(cwd: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    Ok(run_git_checked(cwd, &["status", "--porcelain", "--untracked-files=no"])?.trim().is_empty())
+}
+
+/// Apply a patch from `diff_head` to the working tree
+pub fn apply_patch(cwd: &Path, patch: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    // Paths in the patch are relative to the top; from a subdirectory git
+    // apply would skip everything outside it
+    let top = run_git_checked(cwd, &["rev-parse", "--show-toplevel"])?;
+    run_git_raw(Path::new(top.trim()), &["apply", "--whitespace=nowarn", "-"], patch)?;
+    Ok(())
+}
+
+/// The subset of `commits` present in the object database
+pub fn existing_commits(cwd: &Path, commits: &[String]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    if commits.is_empty() {
+        return Ok(Vec::new());
+    }
+    let input: String = commits.iter().map(|c| format!("{}\n", c)).collect();
+    let out = run_git_with_input(cwd, &["cat-file", "--batch-check=%(objectname) %(objecttype)"], &input)?;
+    Ok(out
+        .lines()
+        .filter_map(|l| l.strip_suffix(" commit"))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Of `commits`, those no local branch, tag, remote-tracking branch or HEAD
+/// reaches any more (e.g. left behind by a rebase). Empty before the first commit.
+pub fn unreachable_commits(cwd: &Path, commits: &[String]) -> Vec<String> {
+    let Some(history) = run_git_command(cwd, &["rev-list", "--branches", "--tags", "--remotes", "HEAD"]) else {
+        return Vec::new();
+    };
+    let history: HashSet<&str> = history.lines().collect();
+    commits.iter().filter(|c| !history.contains(c.as_str())).cloned().collect()
+}
+
+/// Rewritten equivalents of `commits` on branches, tags and remotes, matched
+/// by `git patch-id` (same change, different parent). Returns `(old, new)`
+/// pairs; commits that are gone from the object database, merges and
+/// squashed commits don't match.
+pub fn match_by_patch_id(cwd: &Path, commits: &[String]) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let present = existing_commits(cwd, commits)?;
+    if present.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut args = vec!["log", "--no-walk=unsorted", "-p", "--no-ext-diff"];
+    args.extend(present.iter().map(String::as_str));
+    let old = patch_ids(cwd, &run_git_checked(cwd, &args)?)?;
+
+    // A rewritten commit is committed after the commit it replaces
+    let mut args = vec!["log", "--no-walk", "--format=%cI"];
+    args.extend(present.iter().map(String::as_str));
+    let since = run_git_checked(cwd, &args)?
+        .lines()
+        .filter_map(|l| DateTime::parse_from_rfc3339(l).ok())
+        .min()
+        .ok_or("Could not read commit dates")?;
+    let since = format!("--since={}", since.to_rfc3339());
+    let candidates = run_git_checked(cwd, &["log", "--branches", "--tags", "--remotes", "HEAD", &since, "-p", "--no-ext-diff"])?;
+    let new = patch_ids(cwd, &candidates)?;
+
+    let mut pairs = Vec::new();
+    for (patch_id, old_commit) in old {
+        // Candidates are newest first; the latest rewrite wins
+        if let Some((_, new_commit)) = new.iter().find(|(p, c)| *p == patch_id && *c != old_commit) {
+            pairs.push((old_commit, new_commit.clone()));
+        }
+    }
+    Ok(pairs)
+}
+
+/// `(patch id, commit)` for each commit in `git log -p` output
+fn patch_ids(cwd: &Path, log: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let out = run_git_with_input(cwd, &["patch-id", "--stable"], log)?;
+    Ok(out
+        .lines()
+        .filter_map(|l| l.split_once(' '))
+        .map(|(p, c)| (p.to_string(), c.to_string()))
+        .collect())
+}
+
+/// `(old, new)` pairs from the stdin of a `post-rewrite` hook
+pub fn parse_rewrites(input: &str) -> Vec<(String, String)> {
+    input
+        .lines()
+        .filter_map(|l| {
+            let mut fields = l.split_whitespace();
+            Some((fields.next()?.to_string(), fields.next()?.to_string()))
+        })
+        .collect()
+}
+
+/// Commits carrying a note under `refs/notes/<notes_ref>`
+pub fn noted_commits(cwd: &Path, notes_ref: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let out = run_git_checked(cwd, &["notes", &format!("--ref={}", notes_ref), "list"])?;
+    Ok(out.lines().filter_map(|l| l.split_once(' ')).map(|(_, commit)| commit.to_string()).collect())
+}
+
+/// The note on a commit, or None if it has none
+pub fn read_note(cwd: &Path, notes_ref: &str, commit: &str) -> Option<String> {
+    run_git_command(cwd, &["notes", &format!("--ref={}", notes_ref), "show", commit])
+}
+
+/// Replace the note on a commit
+pub fn write_note(cwd: &Path, notes_ref: &str, commit: &str, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+    run_git_with_input(cwd, &["notes", &format!("--ref={}", notes_ref), "add", "-f", "-F", "-", commit], content)?;
+    Ok(())
+}
+
+const COMMIT_FORMAT: &str = "--format=%H%x1f%h%x1f%aI%x1f%s";
+
+/// One `%H%x1f%h%x1f%aI%x1f%s` line
+fn parse_commit_line(line: &str) -> Option<Commit> {
+    let mut fields = line.splitn(4, '\x1f');
+    let sha = fields.next()?.to_string();
+    let short = fields.next()?.to_string();
+    let author_time = DateTime::parse_from_rfc3339(fields.next()?).ok()?.with_timezone(&Utc);
+    let subject = fields.next().unwrap_or("").to_string();
+    Some(Commit { sha, short, subject, author_time })
+}
+
+/// Reduce the many spellings of a remote to `host/path`:
+/// `git@github.com:org/app.git`, `https://user@GitHub.com/org/app/` and
+/// `ssh://git@github.com:22/org/app` all become `github.com/org/app`.
+/// Local paths (and `file://` URLs) keep their path without a `.git` suffix.
+pub fn normalize_remote_url(url: &str) -> String {
+    let url = url.trim();
+    let strip = |path: &str| {
+        let path = path.trim_end_matches('/');
+        path.strip_suffix(".git").unwrap_or(path).trim_end_matches('/').to_string()
+    };
+
+    let (host, path) = if let Some((_, rest)) = url.split_once("://") {
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let host = authority.rsplit('@').next().unwrap_or(authority);
+        (host.split(':').next().unwrap_or(host), path)
+    } else if let Some((authority, path)) = url.split_once(':').filter(|(a, _)| !a.contains('/') && a.len() > 1) {
+        // scp-like `[user@]host:path`
+        (authority.rsplit('@').next().unwrap_or(authority), path)
+    } else {
+        return strip(url);
+    };
+
+    if host.is_empty() {
+        return strip(&format!("/{}", path.trim_start_matches('/')));
+    }
+    format!("{}/{}", host.to_lowercase(), strip(path.trim_start_matches('/')))
+}
+
+/// Whether a `--repo` argument is a remote URL rather than a path or id
+pub fn looks_like_remote(s: &str) -> bool {
+    s.contains("://") || s.split_once(':').is_some_and(|(a, _)| a.contains('@') && !a.contains('/'))
+}
+
+/// In-process discovery: root, HEAD, branch and upstream from the files under
+/// `.git`. Returns `None` outside a repository or before the first commit.
+fn discover(cwd: &Path) -> Option<RepoInfo> {
+    let cwd = cwd.canonicalize().ok()?;
+    let (_, git_dir) = find_git_dir(&cwd)?;
+    let common_dir = common_dir(&git_dir);
+
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    let (branch, commit) = match head.strip_prefix("ref: ") {
+        Some(refname) => (
+            refname.strip_prefix("refs/heads/").map(str::to_string),
+            resolve_ref(&git_dir, &common_dir, refname)?,
+        ),
+        None => (None, head.to_string()),
+    };
+    let upstream = branch.as_deref().and_then(|b| upstream_of(&common_dir, b));
+    let layout = layout_native(&cwd)?;
 
-pub fn detect_repo_info(cwd: &Path) -> Option<RepoInfo> {
-    let root = run_git_command(cwd, &["rev-parse", "--show-toplevel"])?;
-    let branch = run_git_command(cwd, &["rev-parse", "--abbrev-ref", "HEAD"]);
-    let commit = run_git_command(cwd, &["rev-parse", "HEAD"])?;
-    
     Some(RepoInfo {
-        root: root.trim().to_string(),
-        branch: branch.map(|b| {
-            let b = b.trim().to_string();
-            if b == "HEAD" { None } else { Some(b) }
-        }).flatten(),
-        commit: commit.trim().to_string(),
+        root: layout.root,
+        worktree: layout.worktree,
+        superproject: layout.superproject,
+        branch,
+        commit,
+        change: None,
+        staged: None,
+        unstaged: None,
+        untracked: None,
+        upstream,
+        ahead: None,
+        behind: None,
+        describe: None,
+        files: Vec::new(),
+        id: None,
     })
 }
 
+/// `(worktree top, git directory, common directory)` of the worktree at
+/// `cwd`; the last two differ in linked worktrees
+pub fn git_dirs(cwd: &Path) -> Option<(PathBuf, PathBuf, PathBuf)> {
+    let (worktree, git_dir) = find_git_dir(&cwd.canonicalize().ok()?)?;
+    let git_dir = git_dir.canonicalize().ok()?;
+    let common = common_dir(&git_dir).canonicalize().ok()?;
+    Some((worktree, git_dir, common))
+}
+
+/// Walk up to the first directory with a `.git` dir (or a `.git` file
+/// pointing elsewhere, as in linked worktrees and submodules)
+fn find_git_dir(cwd: &Path) -> Option<(PathBuf, PathBuf)> {
+    // Inside the git directory itself there is no work tree
+    if cwd.components().any(|c| c == Component::Normal(".git".as_ref())) {
+        return None;
+    }
+
+    for dir in cwd.ancestors() {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() && dot_git.join("HEAD").is_file() {
+            return Some((dir.to_path_buf(), dot_git));
+        }
+        if dot_git.is_file() {
+            let content = fs::read_to_string(&dot_git).ok()?;
+            let target = content.trim().strip_prefix("gitdir: ")?;
+            return Some((dir.to_path_buf(), dir.join(target)));
+        }
+    }
+    None
+}
+
+/// Shared repository directory (refs, packed-refs, config). Linked worktrees
+/// point at it through a `commondir` file.
+fn common_dir(git_dir: &Path) -> PathBuf {
+    fs::read_to_string(git_dir.join("commondir"))
+        .map(|c| git_dir.join(c.trim()))
+        .unwrap_or_else(|_| git_dir.to_path_buf())
+}
+
+/// Resolve a ref to a commit id through loose refs, symbolic refs and packed-refs
+fn resolve_ref(git_dir: &Path, common_dir: &Path, refname: &str) -> Option<String> {
+    let mut refname = refname.to_string();
+    for _ in 0..5 {
+        let per_worktree = refname == "HEAD" || refname.starts_with("refs/worktree/") || refname.starts_with("refs/bisect/");
+        let base = if per_worktree { git_dir } else { common_dir };
+
+        match fs::read_to_string(base.join(&refname)) {
+            Ok(content) => match content.trim().strip_prefix("ref: ") {
+                Some(target) => refname = target.to_string(),
+                None => return Some(content.trim().to_string()),
+            },
+            Err(_) => return packed_ref(common_dir, &refname),
+        }
+    }
+    None
+}
+
+fn packed_ref(common_dir: &Path, refname: &str) -> Option<String> {
+    let packed = fs::read_to_string(common_dir.join("packed-refs")).ok()?;
+    packed
+        .lines()
+        .filter(|l| !l.starts_with('#') && !l.starts_with('^'))
+        .filter_map(|l| l.split_once(' '))
+        .find(|(_, name)| *name == refname)
+        .map(|(sha, _)| sha.to_string())
+}
+
+/// `branch.<name>.remote` / `branch.<name>.merge` from the repo config,
+/// shortened the way git prints it (`origin/main`)
+fn upstream_of(common_dir: &Path, branch: &str) -> Option<String> {
+    let merge = config_value(common_dir, "branch", Some(branch), "merge")?;
+    let short = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+    match config_value(common_dir, "branch", Some(branch), "remote")?.as_str() {
+        "." => Some(short.to_string()),
+        remote => Some(format!("{}/{}", remote, short)),
+    }
+}
+
+/// Look up `section.subsection.key` in the repository's `config` file.
+/// Enough of git's format for remotes and branches; includes are not followed.
+fn config_value(common_dir: &Path, section: &str, subsection: Option<&str>, key: &str) -> Option<String> {
+    let config = fs::read_to_string(common_dir.join("config")).ok()?;
+    let wanted_sub = subsection.map(|sub| format!("\"{}\"", sub));
+
+    let mut in_section = false;
+    let mut value = None;
+    for line in config.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            let header = line.trim_start_matches('[').trim_end_matches(']').trim();
+            let (kind, sub) = match header.split_once(' ') {
+                Some((kind, sub)) => (kind, Some(sub.trim().to_string())),
+                None => (header, None),
+            };
+            in_section = kind.eq_ignore_ascii_case(section) && sub == wanted_sub;
+        } else if in_section {
+            if let Some((k, v)) = line.split_once('=') {
+                if k.trim().eq_ignore_ascii_case(key) {
+                    // Last one wins, as in git
+                    value = Some(v.trim().trim_matches('"').to_string());
+                }
+            }
+        }
+    }
+    value
+}
+
+/// Parse `git status --porcelain=v2 --branch -z`: NUL-terminated records with
+/// paths verbatim, where a rename is followed by a record holding its old
+/// path. Returns `None` before the
+/// first commit, when there is no HEAD to record.
+fn parse_status(status: &str) -> Option<RepoInfo> {
+    let mut info = RepoInfo {
+        root: String::new(),
+        worktree: String::new(),
+        superproject: None,
+        branch: None,
+        commit: String::new(),
+        change: None,
+        staged: Some(0),
+        unstaged: Some(0),
+        untracked: Some(0),
+        upstream: None,
+        ahead: None,
+        behind: None,
+        describe: None,
+        files: Vec::new(),
+        id: None,
+    };
+    let bump = |n: &mut Option<u32>| *n = Some(n.unwrap_or(0) + 1);
+
+    let mut records = status.split('\0');
+    while let Some(line) = records.next() {
+        if let Some(header) = line.strip_prefix("# ") {
+            let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+            match key {
+                "branch.oid" if value != "(initial)" => info.commit = value.to_string(),
+                "branch.head" if value != "(detached)" => info.branch = Some(value.to_string()),
+                "branch.upstream" => info.upstream = Some(value.to_string()),
+                "branch.ab" => {
+                    let mut counts = value.split(' ');
+                    info.ahead = counts.next().and_then(|a| a.trim_start_matches('+').parse().ok());
+                    info.behind = counts.next().and_then(|b| b.trim_start_matches('-').parse().ok());
+                }
+                _ => {}
+            }
+        } else if line.starts_with("? ") {
+            bump(&mut info.untracked);
+        } else if let Some(rest) = line.strip_prefix("1 ").or_else(|| line.strip_prefix("2 ")) {
+            let xy = rest.as_bytes();
+            if xy.first().is_some_and(|&x| x != b'.') { bump(&mut info.staged); }
+            if xy.get(1).is_some_and(|&y| y != b'.') { bump(&mut info.unstaged); }
+            // Ordinary entries have 7 fields before the path, renames 8
+            let fields = if line.starts_with('1') { 8 } else { 9 };
+            if let Some(path) = line.splitn(fields + 1, ' ').nth(fields) {
+                info.files.push(path.to_string());
+            }
+            if line.starts_with('2') {
+                records.next();
+            }
+        } else if line.starts_with("u ") {
+            // Unmerged paths need attention in both the index and the tree
+            bump(&mut info.staged);
+            bump(&mut info.unstaged);
+            if let Some(path) = line.splitn(11, ' ').nth(10) {
+                info.files.push(path.to_string());
+            }
+        }
+    }
+
+    if info.commit.is_empty() { None } else { Some(info) }
+}
+
 fn run_git_command(cwd: &Path, args: &[&str]) -> Option<String> {
     Command::new("git")
         .current_dir(cwd)
@@ -25,4 +682,323 @@ fn run_git_command(cwd: &Path, args: &[&str]) -> Option<String> {
         .ok()
         .filter(|output| output.status.success())
         .and_then(|output| String::from_utf8(output.stdout).ok())
-}
\ No newline at end of file
+}
+
+/// Like `run_git_command`, but keeps git's error message for the user
+fn run_git_checked(cwd: &Path, args: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Could not run git: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("git {}: {}", args.first().unwrap_or(&""), stderr.trim()).into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// `run_git_checked` with `input` on stdin
+fn run_git_with_input(cwd: &Path, args: &[&str], input: &str) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(String::from_utf8(run_git_raw(cwd, args, input.as_bytes())?)?)
+}
+
+/// `run_git_with_input` for output that need not be UTF-8
+fn run_git_raw(cwd: &Path, args: &[&str], input: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = Command::new("git")
+        .current_dir(cwd)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not run git: {}", e))?;
+    // Write from a thread so a large output can't deadlock against our input
+    let mut stdin = child.stdin.take().ok_or("git stdin unavailable")?;
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    writer.join().map_err(|_| "git stdin writer panicked")??;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("git {}: {}", args.first().unwrap_or(&""), stderr.trim()).into());
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scratch repository under the temp dir, removed on drop
+    struct TempRepo(PathBuf);
+
+    impl TempRepo {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("clog-git-{}", ulid::Ulid::new()));
+            fs::create_dir_all(&dir).unwrap();
+            let repo = TempRepo(dir.canonicalize().unwrap());
+            repo.git(&["init", "-q", "-b", "main"]);
+            repo
+        }
+
+        fn git(&self, args: &[&str]) {
+            self.git_in(&self.0, args);
+        }
+
+        fn git_in(&self, dir: &Path, args: &[&str]) {
+            let status = Command::new("git")
+                .current_dir(dir)
+                .args(["-c", "user.name=clog", "-c", "user.email=clog@example.com"])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {:?} failed", args);
+        }
+
+        fn commit(&self, msg: &str) {
+            self.git(&["commit", "-q", "--allow-empty", "-m", msg]);
+        }
+    }
+
+    impl Drop for TempRepo {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Native and CLI discovery must agree on everything discovery provides
+    fn assert_same(dir: &Path) {
+        let native = detect_repo_info(dir, false);
+        let cli = detect_repo_info(dir, true);
+        match (native, cli) {
+            (Some(n), Some(c)) => {
+                assert_eq!(n.root, c.root);
+                assert_eq!(n.branch, c.branch);
+                assert_eq!(n.commit, c.commit);
+                assert_eq!(n.upstream, c.upstream);
+                assert_eq!(n.id, c.id);
+                assert_eq!(n.worktree, c.worktree);
+                assert_eq!(n.superproject, c.superproject);
+            }
+            (None, None) => {}
+            (n, c) => panic!("native {:?} vs cli {:?}", n, c),
+        }
+        let (native, cli) = (detect_repo_scope(dir, false), detect_repo_scope(dir, true));
+        assert_eq!(native.as_ref().map(|s| &s.root), cli.as_ref().map(|s| &s.root));
+        assert_eq!(native.and_then(|s| s.id), cli.and_then(|s| s.id));
+    }
+
+    #[test]
+    fn matches_cli_on_branch_and_subdirectory() {
+        let repo = TempRepo::new();
+        repo.commit("first");
+        let sub = repo.0.join("a/b");
+        fs::create_dir_all(&sub).unwrap();
+        assert_same(&repo.0);
+        assert_same(&sub);
+        assert_eq!(discover(&sub).unwrap().branch.as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn matches_cli_with_packed_refs_and_detached_head() {
+        let repo = TempRepo::new();
+        repo.commit("first");
+        repo.git(&["pack-refs", "--all"]);
+        assert!(!repo.0.join(".git/refs/heads/main").exists());
+        assert_same(&repo.0);
+
+        repo.git(&["checkout", "-q", "--detach"]);
+        assert_same(&repo.0);
+        assert_eq!(discover(&repo.0).unwrap().branch, None);
+    }
+
+    #[test]
+    fn matches_cli_in_linked_worktree() {
+        let repo = TempRepo::new();
+        repo.commit("first");
+        let wt = repo.0.join("wt");
+        repo.git(&["worktree", "add", "-q", "-b", "feature", wt.to_str().unwrap()]);
+        repo.git_in(&wt, &["commit", "-q", "--allow-empty", "-m", "on feature"]);
+        assert_same(&wt);
+        let info = discover(&wt).unwrap();
+        assert_eq!(info.branch.as_deref(), Some("feature"));
+        assert_eq!(info.root, display(&repo.0));
+        assert_eq!(info.worktree, display(&wt));
+        // Both checkouts know the worktree, for entries that recorded it as their root
+        for dir in [&repo.0, &wt] {
+            assert_eq!(detect_repo_scope(dir, false).unwrap().worktrees, [display(&wt)]);
+        }
+    }
+
+    #[test]
+    fn matches_cli_in_submodule() {
+        let lib = TempRepo::new();
+        lib.commit("lib");
+        let app = TempRepo::new();
+        app.commit("app");
+        app.git(&["-c", "protocol.file.allow=always", "submodule", "add", "-q", lib.0.to_str().unwrap(), "vendor/lib"]);
+        let sub = app.0.join("vendor/lib");
+        assert_same(&sub);
+        let info = discover(&sub).unwrap();
+        assert_eq!(info.root, display(&sub));
+        assert_eq!(info.superproject, Some(display(&app.0)));
+        assert_eq!(discover(&app.0).unwrap().superproject, None);
+    }
+
+    #[test]
+    fn matches_cli_upstream() {
+        let origin = TempRepo::new();
+        origin.commit("first");
+        let clone = TempRepo::new();
+        clone.git(&["remote", "add", "origin", origin.0.to_str().unwrap()]);
+        clone.git(&["fetch", "-q", "origin"]);
+        clone.git(&["checkout", "-q", "-B", "main", "--track", "origin/main"]);
+        assert_same(&clone.0);
+        assert_eq!(discover(&clone.0).unwrap().upstream.as_deref(), Some("origin/main"));
+    }
+
+    #[test]
+    fn no_repo_info_before_first_commit_or_outside_repo() {
+        let repo = TempRepo::new();
+        assert_same(&repo.0);
+        assert!(discover(&repo.0).is_none());
+        assert_same(&repo.0.join(".git"));
+    }
+
+    #[test]
+    fn normalizes_remote_spellings() {
+        for url in [
+            "git@github.com:robbarry/clog.git",
+            "https://github.com/robbarry/clog",
+            "https://user@GitHub.com/robbarry/clog.git/",
+            "ssh://git@github.com:22/robbarry/clog.git",
+        ] {
+            assert_eq!(normalize_remote_url(url), "github.com/robbarry/clog", "{}", url);
+        }
+        assert_eq!(normalize_remote_url("/srv/git/app.git"), "/srv/git/app");
+        assert_eq!(normalize_remote_url("file:///srv/git/app.git"), "/srv/git/app");
+    }
+
+    #[test]
+    fn repo_id_from_origin_or_root_commit() {
+        let repo = TempRepo::new();
+        repo.commit("first");
+        let root = repo_id(&repo.0, true).unwrap();
+        assert!(root.starts_with("root:"));
+        assert_eq!(repo_id(&repo.0, false).unwrap(), root);
+        // Cached, so later commits (and lookups) skip the history walk
+        let cache = repo.0.join(".git").join(REPO_ID_CACHE);
+        assert_eq!(format!("root:{}", fs::read_to_string(&cache).unwrap().trim()), root);
+        repo.commit("second");
+        assert_eq!(repo_id(&repo.0, false).unwrap(), root);
+
+        repo.git(&["remote", "add", "origin", "git@github.com:org/app.git"]);
+        assert_eq!(repo_id(&repo.0, false).as_deref(), Some("github.com/org/app"));
+        assert_same(&repo.0);
+    }
+
+    #[test]
+    fn reachable_excludes_other_branches() {
+        let repo = TempRepo::new();
+        repo.commit("first");
+        let first = discover(&repo.0).unwrap().commit;
+        repo.git(&["checkout", "-q", "-b", "side"]);
+        repo.commit("side");
+        let side = discover(&repo.0).unwrap().commit;
+        repo.git(&["checkout", "-q", "main"]);
+
+        let reachable = reachable_from_head(&repo.0, &[first.clone(), side, "0".repeat(40)]).unwrap();
+        assert_eq!(reachable, vec![first]);
+    }
+
+    #[test]
+    fn rebased_commits_match_by_patch_id() {
+        let repo = TempRepo::new();
+        repo.commit("base");
+        repo.git(&["checkout", "-q", "-b", "feature"]);
+        fs::write(repo.0.join("f.txt"), "feature\n").unwrap();
+        repo.git(&["add", "f.txt"]);
+        repo.commit("feature");
+        let old = discover(&repo.0).unwrap().commit;
+        repo.git(&["checkout", "-q", "main"]);
+        repo.commit("main moves on");
+        repo.git(&["checkout", "-q", "feature"]);
+        repo.git(&["rebase", "-q", "main"]);
+        let new = discover(&repo.0).unwrap().commit;
+
+        assert_eq!(unreachable_commits(&repo.0, &[old.clone(), new.clone()]), vec![old.clone()]);
+        assert_eq!(match_by_patch_id(&repo.0, std::slice::from_ref(&old)).unwrap(), vec![(old.clone(), new.clone())]);
+        assert_eq!(parse_rewrites(&format!("{} {}\n", old, new)), vec![(old, new)]);
+    }
+
+    #[test]
+    fn changed_paths_and_file_history() {
+        let repo = TempRepo::new();
+        fs::create_dir_all(repo.0.join("src")).unwrap();
+        fs::write(repo.0.join("src/a.rs"), "a\n").unwrap();
+        fs::write(repo.0.join("old name.rs"), "b\n").unwrap();
+        fs::write(repo.0.join("naïve.rs"), "c\n").unwrap();
+        repo.git(&["add", "."]);
+        repo.commit("add files");
+        fs::write(repo.0.join("src/a.rs"), "a2\n").unwrap();
+        repo.git(&["mv", "old name.rs", "new \"name\".rs"]);
+        fs::write(repo.0.join("naïve.rs"), "c2\n").unwrap();
+        fs::write(repo.0.join("untracked.rs"), "u\n").unwrap();
+
+        // git quotes these names in its line-based output
+        let mut files = detect_repo_info(&repo.0, false).unwrap().files;
+        files.sort();
+        assert_eq!(files, ["naïve.rs", "new \"name\".rs", "src/a.rs"]);
+
+        let sub = repo.0.join("src");
+        assert_eq!(worktree_path(&sub, Path::new("a.rs")).as_deref(), Some("src/a.rs"));
+        assert_eq!(worktree_path(&sub, Path::new("../naïve.rs")).as_deref(), Some("naïve.rs"));
+        assert_eq!(worktree_path(&sub, &repo.0.join("src/a.rs")).as_deref(), Some("src/a.rs"));
+
+        repo.git(&["commit", "-q", "-am", "edit a", "--trailer", "Clog-Entries: 01A, 01B"]);
+        let history = file_commits(&sub, "src/a.rs").unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].entries, ["01A", "01B"]);
+        assert_eq!(history[0].parent.as_deref(), Some(history[1].commit.sha.as_str()));
+        assert_eq!(history[1].parent, None);
+    }
+
+    #[test]
+    fn snapshot_patch_round_trip() {
+        let repo = TempRepo::new();
+        let sub = repo.0.join("src");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(sub.join("staged.rs"), "one\n").unwrap();
+        fs::write(repo.0.join("unstaged.txt"), "two\n").unwrap();
+        fs::write(repo.0.join("image.bin"), [0u8, 1, 2, 255, 0, 3]).unwrap();
+        repo.git(&["add", "."]);
+        repo.commit("base");
+        assert!(is_clean(&sub).unwrap());
+
+        fs::write(sub.join("staged.rs"), "one\nmore\n").unwrap();
+        fs::write(sub.join("new.rs"), "new\n").unwrap();
+        repo.git(&["add", "src"]);
+        fs::write(repo.0.join("unstaged.txt"), "changed\n").unwrap();
+        fs::write(repo.0.join("image.bin"), [9u8, 0, 8, 255, 0, 7, 6]).unwrap();
+        assert!(!is_clean(&sub).unwrap());
+
+        // Taken and re-applied from a subdirectory, the patch still covers the whole tree
+        let patch = diff_head(&sub).unwrap();
+        repo.git(&["reset", "-q", "--hard"]);
+        assert!(is_clean(&sub).unwrap());
+        assert!(!sub.join("new.rs").exists());
+
+        apply_patch(&sub, &patch).unwrap();
+        assert_eq!(fs::read_to_string(sub.join("staged.rs")).unwrap(), "one\nmore\n");
+        assert_eq!(fs::read_to_string(sub.join("new.rs")).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(repo.0.join("unstaged.txt")).unwrap(), "changed\n");
+        assert_eq!(fs::read(repo.0.join("image.bin")).unwrap(), [9u8, 0, 8, 255, 0, 7, 6]);
+        assert!(!is_clean(&sub).unwrap());
+        // Applying twice conflicts instead of doubling the changes
+        assert!(apply_patch(&sub, &patch).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::git;
+
+/// Second line of every hook clog writes; hooks without it are never
+/// overwritten or removed
+const MARKER: &str = "# Installed by clog";
+
+/// Suffix a pre-existing hook is moved to; clog's hook runs it first
+const CHAINED_SUFFIX: &str = ".pre-clog";
+
+/// `prepare-commit-msg`: pre-fill the message from entries logged since HEAD
+pub const PREPARE_COMMIT_MSG: &str = "prepare-commit-msg";
+
+/// Hooks installed by `clog hooks install`, each logging an event entry
+pub const EVENT_HOOKS: [&str; 4] = ["post-commit", "post-checkout", "post-merge", "post-rewrite"];
+
+fn script(name: &str) -> String {
+    let command = match name {
+        PREPARE_COMMIT_MSG => "clog commit-msg --hook \"$@\"".to_string(),
+        _ => format!("clog hook {} \"$@\"", name),
+    };
+    // post-rewrite reads the rewritten commits from stdin; both hooks need it
+    let (capture, feed) = if name == "post-rewrite" {
+        ("input=$(cat)\n", "printf '%s\\n' \"$input\" | ")
+    } else {
+        ("", "")
+    };
+    // A missing clog or an unreachable database must never block git; the
+    // chained hook's exit status is passed on
+    format!(
+        "#!/bin/sh\n{marker}\nstatus=0\n{capture}\
+         if [ -x \"$0{suffix}\" ]; then\n    {feed}\"$0{suffix}\" \"$@\" || status=$?\nfi\n\
+         if command -v clog >/dev/null 2>&1; then\n    {feed}{command} || true\nfi\n\
+         exit $status\n",
+        marker = MARKER, suffix = CHAINED_SUFFIX, capture = capture, feed = feed, command = command,
+    )
+}
+
+/// Write the named hook into the repository at `cwd`. A hook that is already
+/// there is kept as `<name>.pre-clog` and run before clog's.
+pub fn install(cwd: &Path, name: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = git::hooks_dir(cwd)?;
+    let path = dir.join(name);
+    if path.exists() && !is_ours(&path) {
+        let chained = chained_path(&path);
+        if chained.exists() {
+            return Err(format!("{} and {} both exist; not touching either", path.display(), chained.display()).into());
+        }
+        fs::rename(&path, &chained)?;
+    }
+    fs::create_dir_all(&dir)?;
+    fs::write(&path, script(name))?;
+    make_executable(&path)?;
+    Ok(path)
+}
+
+/// Remove the named hook if clog installed it, putting back the hook it was
+/// chained to; returns the removed path
+pub fn uninstall(cwd: &Path, name: &str) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    let path = git::hooks_dir(cwd)?.join(name);
+    if !path.exists() {
+        return Ok(None);
+    }
+    if !is_ours(&path) {
+        return Err(format!("{} was not installed by clog; leaving it alone", path.display()).into());
+    }
+    fs::remove_file(&path)?;
+    let chained = chained_path(&path);
+    if chained.exists() {
+        fs::rename(&chained, &path)?;
+    }
+    Ok(Some(path))
+}
+
+/// Whether clog's version of the named hook is installed in the repository at `cwd`
+pub fn is_installed(cwd: &Path, name: &str) -> bool {
+    git::hooks_dir(cwd).is_ok_and(|dir| is_ours(&dir.join(name)))
+}
+
+fn chained_path(path: &Path) -> PathBuf {
+    let mut chained = path.as_os_str().to_owned();
+    chained.push(CHAINED_SUFFIX);
+    PathBuf::from(chained)
+}
+
+fn is_ours(path: &Path) -> bool {
+    fs::read_to_string(path).is_ok_and(|s| s.lines().nth(1) == Some(MARKER))
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Entry kind and message for a hook invocation, or None when there is
+/// nothing worth logging. `args` are the hook's arguments and `input` its
+/// stdin (post-rewrite only).
+pub fn describe_event(cwd: &Path, name: &str, args: &[String], input: &str) -> Option<(&'static str, String)> {
+    // A rebase commits and checks out along the way; post-rewrite sums it up
+    let rebasing = git::rebase_in_progress(cwd);
+    let arg = |i: usize| args.get(i).map(String::as_str).unwrap_or("");
+
+    match name {
+        "post-commit" if !rebasing => {
+            let commit = git::commit_info(cwd, "HEAD")?;
+            Some(("commit", format!("{} {}", commit.short, commit.subject)))
+        }
+        // Flag 1 is a branch checkout; 0 is checking out files
+        "post-checkout" if !rebasing && arg(2) == "1" => {
+            let target = git::current_branch(cwd).unwrap_or_else(|| format!("{} (detached)", short(arg(1))));
+            if arg(0) == arg(1) {
+                Some(("checkout", target))
+            } else {
+                Some(("checkout", format!("{} ({} → {})", target, short(arg(0)), short(arg(1)))))
+            }
+        }
+        "post-merge" if arg(0) == "1" => Some(("merge", "squash merge (not committed yet)".to_string())),
+        "post-merge" => {
+            let commit = git::commit_info(cwd, "HEAD")?;
+            Some(("merge", format!("{} {}", commit.short, commit.subject)))
+        }
+        // Amends already got a post-commit entry
+        "post-rewrite" if arg(0) == "rebase" => {
+            let rewritten = input.lines().filter(|l| !l.trim().is_empty()).count();
+            let head = git::commit_info(cwd, "HEAD")?;
+            Some(("rewrite", format!(
+                "rebase rewrote {} commit{}, now at {} {}",
+                rewritten,
+                if rewritten == 1 { "" } else { "s" },
+                head.short,
+                head.subject
+            )))
+        }
+        _ => None,
+    }
+}
+
+fn short(sha: &str) -> &str {
+    &sha[..7.min(sha.len())]
+}
@@ -4,27 +4,49 @@ mod session;
 mod git;
 mod device;
 mod credentials;
+mod config;
+mod changelog;
+mod commit_msg;
+mod hooks;
+mod notes;
+mod timeline;
+mod project;
+mod vcs;
+mod snapshot;
+mod spool;
+mod watch;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use chrono::Utc;
+use std::collections::HashMap;
 use std::env;
-use std::io::IsTerminal;
-use std::path::Path;
+use std::io::{BufRead, IsTerminal};
+use std::path::{Path, PathBuf};
 use std::process;
+use config::Config;
 use db::Database;
-use models::LogEntry;
+use models::{EntryFilter, LogEntry, RepoInfo, RepoScope, Session, SessionLookup};
+use session::{Anchor, NamedSession};
 use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
 use std::thread;
 use std::time::Duration;
+use ulid::Ulid;
+
+/// Most entries per batch insert when logging from stdin; a batch also goes
+/// out whenever everything read so far has been handled
+const STDIN_BATCH: usize = 1000;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Fast changelog tool with session tracking")]
 struct Args {
-    #[arg(help = "Message to log")]
+    #[arg(help = "Message to log ('-' logs each line of stdin)")]
     message: Option<String>,
     
     #[arg(long, help = "Register name for current session")]
     name: Option<String>,
+
+    #[arg(long = "as", help = "Log under this session name, skipping process detection", value_name = "NAME")]
+    as_name: Option<String>,
     
     #[arg(short, long, help = "List N recent entries", value_name = "N")]
     list: Option<usize>,
@@ -32,11 +54,35 @@ struct Args {
     #[arg(long, help = "Show entries from all repos (not just current)")]
     all: bool,
     
-    #[arg(long, help = "Filter by specific repo root", value_name = "PATH")]
+    #[arg(long, help = "Filter by repo: path, remote URL, repo id or alias", value_name = "REPO")]
     repo: Option<String>,
     
+    #[arg(long, help = "Only entries from this worktree (default: the current one)", value_name = "PATH", num_args = 0..=1, default_missing_value = ".")]
+    worktree: Option<String>,
+    
+    #[arg(long, help = "Only entries whose commit is reachable from HEAD (of the --repo checkout if given)", overrides_with = "no_reachable")]
+    reachable: bool,
+
+    #[arg(long, help = "Include entries from unreachable commits (overrides the config default)", overrides_with = "reachable")]
+    no_reachable: bool,
+
+    #[arg(long, help = "Filter by branch name", value_name = "NAME")]
+    branch: Option<String>,
+
+    #[arg(long, help = "Filter by commit (sha prefix)", value_name = "SHA")]
+    commit: Option<String>,
+    
     #[arg(long, help = "Filter by session name", value_name = "NAME")]
     filter: Option<String>,
+
+    #[arg(long, help = "Only entries that captured a file matching this glob", value_name = "GLOB")]
+    path: Option<String>,
+
+    #[arg(long, help = "Capture the modified and staged paths with the entry")]
+    files: bool,
+
+    #[arg(long, help = "Store the uncommitted diff against HEAD with the entry")]
+    snapshot: bool,
     
     #[arg(long, help = "Show only today's entries")]
     today: bool,
@@ -50,8 +96,131 @@ struct Args {
     #[arg(long, help = "Stream new entries in real-time (tail -f style)")]
     stream: bool,
 
+    #[arg(long, help = "Show recent entries after logging (overrides the config default)", overrides_with = "no_preview")]
+    preview: bool,
+
+    #[arg(long, help = "Don't show recent entries after logging", overrides_with = "preview")]
+    no_preview: bool,
+
+    #[arg(long = "async", help = "Queue the entry for a background writer and return immediately", overrides_with = "no_async")]
+    async_log: bool,
+
+    #[arg(long, help = "Wait for the database even if log.async is set", overrides_with = "async_log")]
+    no_async: bool,
+
     #[arg(long, help = "Show system information")]
     info: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Inspect session detection
+    Session {
+        #[command(subcommand)]
+        action: SessionCommand,
+    },
+    /// Render the entries logged at the commits of a git range as Markdown
+    Changelog {
+        /// Revision range, e.g. v1.2..v1.3
+        range: String,
+
+        #[arg(long, value_enum, default_value = "commit", help = "Group entries by commit, session or day")]
+        group_by: changelog::GroupBy,
+
+        #[arg(long, help = "Template file with '--- group' and '--- entry' sections", value_name = "FILE")]
+        template: Option<PathBuf>,
+    },
+    /// Draft a commit message from the entries logged since the last commit
+    CommitMsg {
+        #[arg(long, help = "Install a prepare-commit-msg hook that pre-fills the editor", conflicts_with_all = ["uninstall", "hook"])]
+        install: bool,
+
+        #[arg(long, help = "Remove the prepare-commit-msg hook", conflicts_with = "hook")]
+        uninstall: bool,
+
+        #[arg(long, help = "Run as prepare-commit-msg: edit the message file in place", num_args = 1..=3, value_names = ["FILE", "SOURCE", "SHA"])]
+        hook: Option<Vec<String>>,
+    },
+    /// Log commits, checkouts, merges and rebases through git hooks
+    Hooks {
+        #[command(subcommand)]
+        action: HooksCommand,
+    },
+    /// Commits and entries of the current repo in one chronological stream
+    Timeline {
+        #[arg(long, default_value_t = 1, help = "How many days back to go (24 hours each)", value_name = "N")]
+        days: u32,
+    },
+    /// Point entries at the rewritten versions of commits a rebase or amend left behind
+    Remap {
+        #[arg(long, help = "Show what would be remapped without changing anything")]
+        dry_run: bool,
+    },
+    /// Entries linked to a file, directly or through the commits that touched it
+    Blame {
+        file: PathBuf,
+    },
+    /// Re-apply working-tree snapshots taken with --snapshot
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotCommand,
+    },
+    /// Log checkouts, commits, resets and stashes as they happen, without hooks
+    WatchRepo {
+        /// Repositories to watch (default: the current one)
+        paths: Vec<PathBuf>,
+    },
+    /// Share entries through git notes on the commits they were logged at
+    Notes {
+        #[command(subcommand)]
+        action: NotesCommand,
+    },
+    /// Wait until every entry logged with --async is in the database
+    Flush,
+    /// Entry point for the installed git hooks
+    #[command(hide = true)]
+    Hook {
+        name: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Background process writing queued --async entries
+    #[command(hide = true)]
+    SpoolWriter,
+}
+
+#[derive(Subcommand, Debug)]
+enum SnapshotCommand {
+    /// Apply a snapshot to a working tree without uncommitted changes
+    Apply {
+        /// Snapshot id or a prefix of it (at least 4 digits)
+        id: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum NotesCommand {
+    /// Write this repository's entries as notes under refs/notes/clog
+    Export,
+    /// Read entries from refs/notes/clog into the database
+    Import,
+}
+
+#[derive(Subcommand, Debug)]
+enum HooksCommand {
+    /// Install post-commit, post-checkout, post-merge and post-rewrite hooks in this repository
+    Install,
+    /// Remove clog's hooks from this repository, restoring any hooks they wrapped
+    Uninstall,
+}
+
+#[derive(Subcommand, Debug)]
+enum SessionCommand {
+    /// Print the process ancestry and which anchor rule matched
+    Explain,
 }
 
 fn main() {
@@ -69,19 +238,88 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
         handle_info_command()?;
         return Ok(());
     }
-    
+
+    let config = config::load()?;
+
+    if let Some(Command::Session { action: SessionCommand::Explain }) = &args.command {
+        return handle_session_explain(&config, &args);
+    }
+    if let Some(Command::CommitMsg { install, uninstall, .. }) = &args.command {
+        if *install || *uninstall {
+            return handle_hook_install(&[hooks::PREPARE_COMMIT_MSG], *install);
+        }
+    }
+    if let Some(Command::Hooks { action }) = &args.command {
+        return handle_hook_install(&hooks::EVENT_HOOKS, matches!(action, HooksCommand::Install));
+    }
+    if let Some(Command::Snapshot { action: SnapshotCommand::Apply { id } }) = &args.command {
+        return handle_snapshot_apply(id);
+    }
+    if let Some(Command::Flush) = &args.command {
+        return handle_flush();
+    }
+    if let Some(Command::SpoolWriter) = &args.command {
+        return handle_spool_writer();
+    }
+
+    // A plain `clog "msg"` reads the repository while the database connects
+    let detection = match (&args.message, &args.command) {
+        (Some(_), None) => {
+            let (cwd, use_cli) = (env::current_dir()?, config.git.use_cli);
+            Some(thread::spawn(move || EntryContext::detect(cwd, use_cli)))
+        }
+        _ => None,
+    };
+
+    let async_log = if args.async_log || args.no_async { args.async_log } else { config.log.async_log };
+    if let (Some(message), None, None, true) = (&args.message, &args.command, &args.name, async_log && args.message.as_deref() != Some("-")) {
+        let named = session::named_session(args.as_name.as_deref());
+        let anchor = current_anchor(&config, named.as_ref(), true);
+        let context = detection.map(|d| d.join().map_err(|_| "Repository detection failed")).transpose()?;
+        let options = EntryOptions { files: args.files, snapshot: args.snapshot, context: context.as_ref(), ..Default::default() };
+        return handle_async_log(&config, anchor, named, message, &options);
+    }
+
     let db = Database::new()?;
+
+    if let Some(Command::Changelog { range, group_by, template }) = &args.command {
+        return handle_changelog(&db, &config, range, *group_by, template.as_deref());
+    }
+    if let Some(Command::CommitMsg { hook, .. }) = &args.command {
+        return handle_commit_msg(&db, &config, hook.as_deref());
+    }
+    if let Some(Command::Timeline { days }) = &args.command {
+        return handle_timeline(&db, &config, *days);
+    }
+    if let Some(Command::Remap { dry_run }) = &args.command {
+        return handle_remap(&db, &config, *dry_run);
+    }
+    if let Some(Command::Blame { file }) = &args.command {
+        return handle_blame(&db, &config, file);
+    }
+    if let Some(Command::Notes { action }) = &args.command {
+        return match action {
+            NotesCommand::Export => handle_notes_export(&db, &config),
+            NotesCommand::Import => handle_notes_import(&db, &config),
+        };
+    }
+    if let Some(Command::WatchRepo { paths }) = &args.command {
+        return handle_watch_repo(&db, &config, paths, args.as_name.as_deref());
+    }
+    if let Some(Command::Hook { name, args: hook_args }) = &args.command {
+        return handle_git_hook(&db, &config, name, hook_args);
+    }
+
+    let named = session::named_session(args.as_name.as_deref());
     
     // Only need PID for write operations
     if args.name.is_some() || args.message.is_some() {
-        let ppid = session::get_ppid().unwrap_or_else(|| {
-            eprintln!("Warning: Could not get parent PID, using current PID");
-            process::id()
-        });
+        let anchor = current_anchor(&config, named.as_ref(), true);
         
         // Handle both name and message if both are provided
         if let Some(name) = &args.name {
-            handle_name_registration(&db, ppid, name)?;
+            let session = find_session(&db, &anchor, named.as_ref(), true)?;
+            handle_name_registration(&db, &anchor, session.as_ref(), name)?;
             // Only return if there's no message to log
             if args.message.is_none() {
                 return Ok(());
@@ -89,115 +327,517 @@ fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
         }
         
         if let Some(message) = &args.message {
-            handle_log_message(&db, ppid, message)?;
+            let context = detection.map(|d| d.join().map_err(|_| "Repository detection failed")).transpose()?;
+            let options = EntryOptions { files: args.files, snapshot: args.snapshot, context: context.as_ref(), ..Default::default() };
+            if message == "-" {
+                return handle_stdin_log(&db, &config, &anchor, named.as_ref(), &options);
+            }
+            let preview = if args.preview || args.no_preview { args.preview } else { config.log.preview };
+            handle_log_message(&db, &config, &anchor, named.as_ref(), message, &options, preview)?;
         }
     } else if args.stream {
-        handle_stream_entries(&db, &args)?;
+        handle_stream_entries(&db, &config, &args)?;
     } else {
-        handle_list_entries(&db, &args)?;
+        handle_list_entries(&db, &config, &args)?;
     }
     
     Ok(())
 }
 
-fn handle_name_registration(db: &Database, ppid: u32, name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let session = db.get_active_session(ppid)?;
-    
-    let _session_id = if let Some(sess) = session {
-        db.update_session_name(&sess.session_id, name)?;
-        sess.session_id
-    } else {
-        let sid = db.create_session(ppid)?;
-        db.update_session_name(&sid, name)?;
-        sid
+/// Resolve the session for this invocation. An explicit named session
+/// (`--as`, `CLOG_SESSION`, `CLOG_NAME`) bypasses the process tree and is
+/// created on demand when `create` is set; otherwise the active session of
+/// the anchoring parent process is looked up.
+fn current_session(db: &Database, config: &Config, named: Option<&NamedSession>, create: bool) -> Result<(Anchor, Option<Session>), Box<dyn std::error::Error>> {
+    let anchor = current_anchor(config, named, create);
+    let session = find_session(db, &anchor, named, create)?;
+    Ok((anchor, session))
+}
+
+/// The process a session hangs off: for a named session the immediate
+/// parent (the key identifies it), else the process the anchor rules pick
+fn current_anchor(config: &Config, named: Option<&NamedSession>, warn: bool) -> Anchor {
+    if let Some(named) = named {
+        return Anchor { pid: session::immediate_parent(), start_time: 0, name: named.key.clone(), rule: None };
+    }
+    session::get_anchor(&config.session.anchors).unwrap_or_else(|| {
+        if warn {
+            eprintln!("Warning: Could not get parent PID, using current PID");
+        }
+        Anchor { pid: process::id(), start_time: 0, name: "clog".to_string(), rule: None }
+    })
+}
+
+fn find_session(db: &Database, anchor: &Anchor, named: Option<&NamedSession>, create: bool) -> Result<Option<Session>, Box<dyn std::error::Error>> {
+    let Some(named) = named else {
+        return db.get_active_session(anchor.pid, anchor.start_time);
     };
-    
-    println!("✓ Session registered as '{}' (PID: {})", name, ppid);
-    Ok(())
+    let session = match db.get_session_by_key(&named.key)? {
+        Some(mut sess) => {
+            if let Some(name) = &named.name {
+                if sess.name.as_ref() != Some(name) {
+                    db.update_session_name(&sess.session_id, name)?;
+                    sess.name = Some(name.clone());
+                }
+            }
+            Some(sess)
+        }
+        None if create => {
+            let name = named.name.as_deref().unwrap_or(&named.key);
+            db.create_keyed_session(anchor.pid, &named.key, name)?;
+            // Re-read: a concurrent call may have created it under another name
+            db.get_session_by_key(&named.key)?
+        }
+        None => None,
+    };
+    Ok(session)
 }
 
-fn handle_log_message(db: &Database, ppid: u32, message: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let session = db.get_active_session(ppid)?;
-    
-    let (session_id, name) = if let Some(sess) = session {
-        db.update_session_last_seen(&sess.session_id)?;
-        (sess.session_id, sess.name)
+/// How the fast write path finds the session `find_session` would return
+fn session_lookup(anchor: &Anchor, named: Option<&NamedSession>) -> SessionLookup {
+    match named {
+        Some(named) => SessionLookup::Key { key: named.key.clone(), name: named.name.clone() },
+        None => SessionLookup::Anchor { ppid: anchor.pid, start_time: anchor.start_time },
+    }
+}
+
+fn handle_name_registration(db: &Database, anchor: &Anchor, session: Option<&Session>, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let relabeled = if let Some(sess) = session {
+        db.update_session_name(&sess.session_id, name)?
     } else {
-        eprintln!("This appears to be a new session (PID: {})", ppid);
-        eprintln!("Please identify yourself by running:");
-        eprintln!("  clog --name <your-identifier>");
-        eprintln!("Then retry your command.");
-        process::exit(1);
+        db.create_session(anchor.pid, anchor.start_time, Some(name), false)?;
+        0
     };
     
-    let cwd = env::current_dir()?;
-    let repo_info = git::detect_repo_info(&cwd);
-    
-    let entry = LogEntry {
-        id: None,
-        ppid,
-        name: name.clone(),
-        timestamp: Utc::now(),
-        directory: cwd.to_string_lossy().to_string(),
-        message: message.to_string(),
-        session_id,
-        repo_root: repo_info.as_ref().map(|r| r.root.clone()),
-        repo_branch: repo_info.as_ref().and_then(|r| r.branch.clone()),
-        repo_commit: repo_info.as_ref().map(|r| r.commit.clone()),
-        event_id: None,
-        received_at: None,
-    };
-    
-    db.insert_log_entry(&entry)?;
-    println!("✓ Logged");
+    println!("✓ Session registered as '{}' (PID: {})", name, anchor.pid);
+    if relabeled > 0 {
+        println!("  Relabeled {} earlier {}", relabeled, if relabeled == 1 { "entry" } else { "entries" });
+    }
+    Ok(())
+}
+
+fn handle_log_message(db: &Database, config: &Config, anchor: &Anchor, named: Option<&NamedSession>, message: &str, options: &EntryOptions, preview: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let (entry, files) = new_entry(config, anchor, message, options)?;
+    let entry = log_entry(db, anchor, named, entry, &files, true)?;
+    match &entry.snapshot {
+        Some(id) => println!("✓ Logged (snapshot {})", snapshot::short(id)),
+        None => println!("✓ Logged"),
+    }
+    if !preview {
+        return Ok(());
+    }
     println!("Recent entries:");
 
     // After logging, show recent entries from the current context
     let list_args = Args {
         message: None,
         name: None,
+        as_name: None,
         list: None,       // default to 10
         all: false,       // prefer current repo context if in one
         repo: None,
+        worktree: None,
+        reachable: false,
+        no_reachable: false,
+        branch: None,
+        commit: None,
         filter: None,
+        path: None,
+        files: false,
+        snapshot: false,
         today: false,
         session: false,
         verbose: false,   // compact format
         stream: false,
+        preview: false,
+        no_preview: false,
+        async_log: false,
+        no_async: false,
         info: false,
+        command: None,
     };
 
-    handle_list_entries(db, &list_args)
+    handle_list_entries(db, config, &list_args)
 }
 
-fn handle_list_entries(db: &Database, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
-    let limit = args.list.unwrap_or(10);
-    
-    let current_repo = if !args.all && args.repo.is_none() {
-        env::current_dir().ok()
-            .and_then(|cwd| git::detect_repo_info(&cwd))
-            .map(|info| info.root)
-    } else {
-        None
+/// Save a note in its session: one round trip when the session exists,
+/// the full path when it has to be created (or renamed)
+fn log_entry(db: &Database, anchor: &Anchor, named: Option<&NamedSession>, entry: LogEntry, files: &[String], interactive: bool) -> Result<LogEntry, Box<dyn std::error::Error>> {
+    if let Some(entry) = db.insert_into_session(&session_lookup(anchor, named), &entry, files)? {
+        return Ok(entry);
+    }
+    let session = find_session(db, anchor, named, true)?;
+    save_entry(db, anchor, session, entry, files, interactive)
+}
+
+/// Queue a note for the background writer. The entry is complete, timestamp
+/// and event id included, before it is queued; only the session is resolved
+/// later. The fixed event id makes a retried batch skip what already went in.
+fn handle_async_log(config: &Config, anchor: Anchor, named: Option<NamedSession>, message: &str, options: &EntryOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut entry, files) = new_entry(config, &anchor, message, options)?;
+    entry.event_id = Some(Ulid::new().to_string());
+    let snapshot = entry.snapshot.clone();
+    spool::enqueue(&spool::Job { anchor, named, entry, files })?;
+    spool::start_writer()?;
+    match snapshot {
+        Some(id) => println!("✓ Queued (snapshot {})", snapshot::short(&id)),
+        None => println!("✓ Queued"),
+    }
+    Ok(())
+}
+
+/// Write queued entries until the spool stays empty, then exit. Quits at
+/// once when another writer is already at it.
+fn handle_spool_writer() -> Result<(), Box<dyn std::error::Error>> {
+    let Some(mut lock) = spool::lock_writer()? else { return Ok(()) };
+    if spool::is_empty() {
+        return Ok(());
+    }
+    let db = Database::new()?;
+    loop {
+        write_spool(&db, &lock)?;
+        drop(lock);
+        // A client that saw the lock held just before it was released
+        // didn't start a writer, so look again
+        if spool::is_empty() {
+            return Ok(());
+        }
+        match spool::lock_writer()? {
+            Some(next) => lock = next,
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Write each spooled batch with one batch insert, resolving every session
+/// in it once
+fn write_spool(db: &Database, lock: &spool::WriterLock) -> Result<usize, Box<dyn std::error::Error>> {
+    spool::drain(lock, |jobs| {
+        let mut sessions = HashMap::new();
+        let mut entries = Vec::with_capacity(jobs.len());
+        for job in jobs {
+            let lookup = session_lookup(&job.anchor, job.named.as_ref());
+            let (session_id, name) = match sessions.get(&lookup) {
+                Some(session) => session,
+                None => {
+                    let session = find_session(db, &job.anchor, job.named.as_ref(), true)?;
+                    let session = open_session(db, &job.anchor, session, false)?;
+                    sessions.entry(lookup).or_insert(session)
+                }
+            };
+            entries.push(LogEntry {
+                session_id: session_id.clone(),
+                name: name.clone(),
+                event_id: job.entry.event_id.clone().or_else(|| Some(Ulid::new().to_string())),
+                ..job.entry.clone()
+            });
+        }
+        db.insert_log_entries(&entries)?;
+        for (entry, job) in entries.iter().zip(jobs) {
+            if let Some(event_id) = &entry.event_id {
+                db.insert_entry_files(event_id, &job.files)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Log every line read from stdin as an entry of the current session,
+/// inserting them in batches
+fn handle_stdin_log(db: &Database, config: &Config, anchor: &Anchor, named: Option<&NamedSession>, options: &EntryOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if options.files || options.snapshot {
+        return Err("--files and --snapshot can't be used when logging from stdin".into());
+    }
+    // stdin carries the messages, so never prompt for a session name
+    let session = find_session(db, anchor, named, true)?;
+    let (session_id, name) = open_session(db, anchor, session, false)?;
+    let (template, _) = new_entry(config, anchor, "", options)?;
+
+    let mut logged = 0;
+    let mut batch = Vec::with_capacity(STDIN_BATCH);
+    let mut stdin = std::io::BufReader::with_capacity(64 * 1024, std::io::stdin().lock());
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            break;
+        }
+        if !line.trim().is_empty() {
+            batch.push(LogEntry {
+                session_id: session_id.clone(),
+                name: name.clone(),
+                message: line.trim_end().to_string(),
+                timestamp: Utc::now(),
+                ..template.clone()
+            });
+        }
+        // Write what has arrived before waiting for more, so a slow pipe
+        // (`tail -f log | clog -`) is logged as it goes and an interrupt
+        // loses nothing already read
+        if batch.len() == STDIN_BATCH || (!batch.is_empty() && stdin.buffer().is_empty()) {
+            logged += db.insert_log_entries(&batch)?;
+            batch.clear();
+        }
+    }
+    logged += db.insert_log_entries(&batch)?;
+    println!("✓ Logged {} {}", logged, if logged == 1 { "entry" } else { "entries" });
+    Ok(())
+}
+
+/// Wait for the background writer, then write whatever is still queued
+/// here, so a failing database shows up as an error
+fn handle_flush() -> Result<(), Box<dyn std::error::Error>> {
+    let lock = loop {
+        if let Some(lock) = spool::lock_writer()? {
+            break lock;
+        }
+        thread::sleep(Duration::from_millis(50));
     };
-    
-    let repo_filter = args.repo.as_deref().or(current_repo.as_deref());
-    
-    let session_id = if args.session {
-        // For session filtering, try to get PID but don't warn if it fails
-        let ppid = session::get_ppid().unwrap_or_else(|| process::id());
-        db.get_active_session(ppid)?.map(|s| s.session_id)
+    let written = if spool::is_empty() { 0 } else { write_spool(&Database::new()?, &lock)? };
+    match written {
+        0 => println!("✓ Nothing pending"),
+        1 => println!("✓ Flushed 1 entry"),
+        n => println!("✓ Flushed {} entries", n),
+    }
+    Ok(())
+}
+
+/// What an entry records beyond the message and session
+#[derive(Debug, Default)]
+struct EntryOptions<'a> {
+    /// Event type for hook and watcher entries; `None` for notes
+    kind: Option<&'a str>,
+    /// Directory the entry is logged from (default: the current one)
+    dir: Option<&'a Path>,
+    /// Changed paths (also on with the `log.files` setting)
+    files: bool,
+    /// The uncommitted diff against HEAD
+    snapshot: bool,
+    /// Repository and project, when already detected (default: detect from `dir`)
+    context: Option<&'a EntryContext>,
+}
+
+/// Where an entry is logged from
+#[derive(Debug)]
+struct EntryContext {
+    cwd: PathBuf,
+    vcs: Option<&'static str>,
+    repo: Option<RepoInfo>,
+    project_root: Option<String>,
+}
+
+impl EntryContext {
+    fn detect(cwd: PathBuf, use_cli: bool) -> EntryContext {
+        let (vcs, repo) = vcs::detect_repo_info(&cwd, use_cli).unzip();
+        let project_root = project::detect_project_root(&cwd).map(|p| p.to_string_lossy().to_string());
+        EntryContext { cwd, vcs, repo, project_root }
+    }
+}
+
+/// Save an entry in the current session, creating the session if needed,
+/// and return it. Notes (no `kind`) may prompt for a session name; events
+/// never do.
+fn write_entry(db: &Database, config: &Config, anchor: &Anchor, session: Option<Session>, message: &str, options: &EntryOptions) -> Result<LogEntry, Box<dyn std::error::Error>> {
+    let (entry, files) = new_entry(config, anchor, message, options)?;
+    save_entry(db, anchor, session, entry, &files, options.kind.is_none())
+}
+
+/// The entry to write, before it has a session, and the paths to record with it
+fn new_entry(config: &Config, anchor: &Anchor, message: &str, options: &EntryOptions) -> Result<(LogEntry, Vec<String>), Box<dyn std::error::Error>> {
+    let detected;
+    let context = match options.context {
+        Some(context) => context,
+        None => {
+            let cwd = match options.dir {
+                Some(dir) => dir.to_path_buf(),
+                None => env::current_dir()?,
+            };
+            detected = EntryContext::detect(cwd, config.git.use_cli);
+            &detected
+        }
+    };
+    // Before anything is written: an oversized diff fails the whole entry
+    let snapshot = if options.snapshot { take_snapshot(&context.cwd, config)? } else { None };
+    let repo_info = context.repo.as_ref();
+
+    let entry = LogEntry {
+        ppid: anchor.pid,
+        name: None,
+        timestamp: Utc::now(),
+        directory: context.cwd.to_string_lossy().to_string(),
+        message: message.to_string(),
+        session_id: String::new(),
+        vcs: context.vcs.map(str::to_string),
+        repo_root: repo_info.map(|r| r.root.clone()),
+        repo_branch: repo_info.and_then(|r| r.branch.clone()),
+        repo_commit: repo_info.map(|r| r.commit.clone()),
+        repo_commit_original: None,
+        repo_change: repo_info.and_then(|r| r.change.clone()),
+        project_root: context.project_root.clone(),
+        repo_id: repo_info.and_then(|r| r.id.clone()),
+        repo_worktree: repo_info.map(|r| r.worktree.clone()),
+        repo_superproject: repo_info.and_then(|r| r.superproject.clone()),
+        repo_staged: repo_info.and_then(|r| r.staged),
+        repo_unstaged: repo_info.and_then(|r| r.unstaged),
+        repo_untracked: repo_info.and_then(|r| r.untracked),
+        repo_upstream: repo_info.and_then(|r| r.upstream.clone()),
+        repo_ahead: repo_info.and_then(|r| r.ahead),
+        repo_behind: repo_info.and_then(|r| r.behind),
+        repo_describe: repo_info.and_then(|r| r.describe.clone()),
+        kind: options.kind.map(str::to_string),
+        snapshot,
+        event_id: None,
+        received_at: None,
+    };
+    let files = match repo_info {
+        Some(info) if options.files || config.log.files => info.files.clone(),
+        _ => Vec::new(),
+    };
+    Ok((entry, files))
+}
+
+/// Write a new entry into `session`, or into a new session for the anchor
+fn save_entry(db: &Database, anchor: &Anchor, session: Option<Session>, mut entry: LogEntry, files: &[String], interactive: bool) -> Result<LogEntry, Box<dyn std::error::Error>> {
+    let (session_id, name) = open_session(db, anchor, session, interactive)?;
+    entry.session_id = session_id;
+    entry.name = name;
+
+    let event_id = db.insert_log_entry(&entry)?;
+    db.insert_entry_files(&event_id, files)?;
+    entry.event_id = Some(event_id);
+    Ok(entry)
+}
+
+/// Id and name of the session to write into: `session`, marked as seen, or
+/// a new session for the anchor
+fn open_session(db: &Database, anchor: &Anchor, session: Option<Session>, interactive: bool) -> Result<(String, Option<String>), Box<dyn std::error::Error>> {
+    if let Some(sess) = session {
+        db.update_session_last_seen(&sess.session_id)?;
+        Ok((sess.session_id, sess.name))
     } else {
-        None
+        // Never drop the entry: name the session now and let --name fix it later
+        let derived = session::auto_name(anchor);
+        let prompted = if interactive { session::prompt_for_name(&derived) } else { None };
+        let (name, auto_named) = match prompted {
+            Some(n) => (n, false),
+            None => (derived, true),
+        };
+        let session_id = db.create_session(anchor.pid, anchor.start_time, Some(&name), auto_named)?;
+        if auto_named && interactive {
+            eprintln!("New session (PID: {}) named '{}'.", anchor.pid, name);
+            eprintln!("Rename it (and this entry) with: clog --name <your-identifier>");
+        }
+        Ok((session_id, Some(name)))
+    }
+}
+
+/// Store the diff of the working tree against HEAD; None (with a warning)
+/// when there is nothing uncommitted
+fn take_snapshot(cwd: &Path, config: &Config) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    git::head_commit(cwd, config.git.use_cli).ok_or("--snapshot needs a git repository with a commit")?;
+    let patch = git::diff_head(cwd)?;
+    if patch.is_empty() {
+        eprintln!("Warning: no uncommitted changes to tracked files; logging without a snapshot");
+        return Ok(None);
+    }
+    if patch.len() as u64 > config.snapshot.max_bytes {
+        return Err(format!(
+            "Diff is {} bytes, over the {} byte snapshot limit (snapshot.max_bytes in {})",
+            patch.len(), config.snapshot.max_bytes, config::get_config_path().display()
+        ).into());
+    }
+    Ok(Some(snapshot::store(&patch)?))
+}
+
+/// Re-apply a stored snapshot. Refuses a tree with uncommitted changes, so
+/// the result is exactly the snapshotted state when HEAD matches.
+fn handle_snapshot_apply(id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let cwd = env::current_dir()?;
+    let (id, patch) = snapshot::load(id)?;
+    if !git::is_clean(&cwd)? {
+        return Err("Working tree has uncommitted changes; commit or stash them before applying a snapshot".into());
+    }
+    git::apply_patch(&cwd, &patch)?;
+    println!("✓ Applied snapshot {}", snapshot::short(&id));
+    Ok(())
+}
+
+/// Log the event behind a git hook invocation. Stays quiet: git prints
+/// whatever hooks write.
+fn handle_git_hook(db: &Database, config: &Config, name: &str, hook_args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut input = String::new();
+    if name == "post-rewrite" {
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut input)?;
+    }
+    let cwd = env::current_dir()?;
+    if name == "post-rewrite" {
+        if let Some(scope) = git::detect_repo_scope(&cwd, config.git.use_cli) {
+            db.remap_commits(&scope, &git::parse_rewrites(&input))?;
+        }
+    }
+    let Some((kind, message)) = hooks::describe_event(&cwd, name, hook_args, &input) else {
+        return Ok(());
     };
+
+    let (anchor, session) = match session::named_session(None) {
+        Some(named) => current_session(db, config, Some(&named), true)?,
+        None => {
+            let anchor = session::get_hook_anchor(&config.session.anchors)
+                .ok_or("Could not read the process tree")?;
+            let session = db.get_active_session(anchor.pid, anchor.start_time)?;
+            (anchor, session)
+        }
+    };
+    write_entry(db, config, &anchor, session, &message, &EntryOptions { kind: Some(kind), ..Default::default() })?;
+    Ok(())
+}
+
+/// Follow the HEAD and stash reflogs of each repository and log what
+/// changes, under the "git" session. Runs until interrupted.
+fn handle_watch_repo(db: &Database, config: &Config, paths: &[PathBuf], as_name: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    use notify::{RecursiveMode, Watcher};
+    use std::sync::mpsc;
+
+    let paths = if paths.is_empty() { vec![env::current_dir()?] } else { paths.to_vec() };
+    let mut repos = paths.iter().map(|p| watch::WatchedRepo::open(p)).collect::<Result<Vec<_>, _>>()?;
+    let named = session::named_session(as_name)
+        .unwrap_or(NamedSession { key: watch::SESSION.to_string(), name: Some(watch::SESSION.to_string()) });
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    for repo in &repos {
+        watcher.watch(&repo.git_dir, RecursiveMode::NonRecursive)?;
+        watcher.watch(&repo.refs_dir, RecursiveMode::Recursive)?;
+        if hooks::EVENT_HOOKS.iter().any(|name| hooks::is_installed(&repo.worktree, name)) {
+            eprintln!("Warning: clog hooks are installed in {}; its commits and checkouts will be logged twice", repo.worktree.display());
+        }
+        println!("Watching {}", repo.worktree.display());
+    }
+
+    while rx.recv().is_ok() {
+        // Let a burst (a rebase, a pull) settle before reading the reflogs
+        while rx.recv_timeout(Duration::from_millis(200)).is_ok() {}
+        for repo in &mut repos {
+            for (kind, message) in repo.poll() {
+                let options = EntryOptions { kind: Some(kind), dir: Some(&repo.worktree), ..Default::default() };
+                let logged = current_session(db, config, Some(&named), true)
+                    .and_then(|(anchor, session)| write_entry(db, config, &anchor, session, &message, &options));
+                match logged {
+                    Ok(entry) => print_compact_entry(&entry),
+                    // A database hiccup costs this event, not the watcher
+                    Err(e) => eprintln!("Warning: Could not log {} in {}: {}", kind, repo.worktree.display(), e),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_list_entries(db: &Database, config: &Config, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let limit = args.list.unwrap_or(10);
     
-    let mut entries = db.list_entries(
-        limit,
-        repo_filter,
-        args.filter.as_deref(),
-        args.today,
-        session_id.as_deref()
-    )?;
+    let filter = entry_filter(db, config, args)?;
+    let mut entries = db.list_entries(limit, &filter)?;
     
     entries.reverse();
     
@@ -211,57 +851,229 @@ fn handle_list_entries(db: &Database, args: &Args) -> Result<(), Box<dyn std::er
 
             println!("[{}] {} ({}) {}", time_str, name_str, dir_str, ppid_str);
             
-            if let (Some(root), Some(commit)) = (&entry.repo_root, &entry.repo_commit) {
+            // jj entries from before the git commit was recorded only have the change id
+            if let (Some(root), Some(commit)) = (&entry.repo_root, entry.repo_commit.as_ref().or(entry.repo_change.as_ref())) {
                 let branch = entry.repo_branch.as_deref().unwrap_or("detached");
-                println!("  repo: {}  branch: {}  commit: {}",
+                let vcs = match entry.vcs.as_deref() {
+                    Some(v) if v != "git" => format!(" ({})", v),
+                    _ => String::new(),
+                };
+                let change = match &entry.repo_change {
+                    Some(change) if entry.repo_commit.is_some() => format!("  change: {}", &change[..8.min(change.len())]),
+                    _ => String::new(),
+                };
+                println!("  repo: {}{}  branch: {}  commit: {}{}",
                     shorten_path(root),
+                    vcs,
                     branch,
-                    &commit[..7.min(commit.len())]
+                    &commit[..7.min(commit.len())],
+                    change
                 );
+                if let Some(original) = &entry.repo_commit_original {
+                    println!("  logged at: {} (since rewritten)", &original[..7.min(original.len())]);
+                }
+                if let Some(wt) = entry.repo_worktree.as_deref().filter(|wt| Some(*wt) != entry.repo_root.as_deref()) {
+                    println!("  worktree: {}", shorten_path(wt));
+                }
+                if let Some(sp) = &entry.repo_superproject {
+                    println!("  superproject: {}", shorten_path(sp));
+                }
+                if let Some(upstream) = &entry.repo_upstream {
+                    println!("  upstream: {} (ahead {}, behind {})",
+                        upstream,
+                        entry.repo_ahead.unwrap_or(0),
+                        entry.repo_behind.unwrap_or(0)
+                    );
+                }
+                if let Some(describe) = &entry.repo_describe {
+                    println!("  describe: {}", describe);
+                }
+                if let Some(id) = &entry.snapshot {
+                    println!("  snapshot: {}", snapshot::short(id));
+                }
+                if entry.repo_staged.is_some() {
+                    println!("  tree: {} staged, {} unstaged, {} untracked",
+                        entry.repo_staged.unwrap_or(0),
+                        entry.repo_unstaged.unwrap_or(0),
+                        entry.repo_untracked.unwrap_or(0)
+                    );
+                }
             }
             
-            println!("  {}", entry.message);
+            if let (None, Some(project)) = (&entry.repo_root, &entry.project_root) {
+                println!("  project: {}", shorten_path(project));
+            }
+            match &entry.kind {
+                Some(kind) => println!("  {}: {}", colorize(kind, "33", use_color), entry.message),
+                None => println!("  {}", entry.message),
+            }
             println!();
         } else {
-            let use_color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
-            let icon = branch_icon(); // branch glyph (or ASCII if CLOG_ASCII is set)
-            let name_ppid = format_name_ppid(entry.name.as_deref(), entry.ppid, use_color);
-
-            // Repo basename (if available), truncate to 20 chars
-            let repo_name = entry
-                .repo_root
-                .as_deref()
-                .and_then(|r| Path::new(r).file_name())
-                .and_then(|os| os.to_str())
-                .map(|s| truncate_ellipsize(s, 20));
-
-            // Branch name (if available), truncate to 20 chars
-            let branch_name = entry
-                .repo_branch
-                .as_deref()
-                .map(|s| truncate_ellipsize(s, 20));
-
-            // Build compact inline repo/branch segment: " (repo  branch)"
-            let meta_str = match (repo_name.as_deref(), branch_name.as_deref()) {
-                (Some(r), Some(b)) => format!(" ({} {} {})", colorize(r, "1;32", use_color), colorize(icon, "35", use_color), colorize(b, "35", use_color)),
-                (Some(r), None) => format!(" ({})", colorize(r, "1;32", use_color)),
-                (None, Some(b)) => format!(" ({} {})", colorize(icon, "35", use_color), colorize(b, "35", use_color)),
-                (None, None) => String::new(),
-            };
-
-            println!(
-                "{} {}{} {}",
-                colorize(&entry.timestamp.format("%H:%M:%S").to_string(), "90", use_color),
-                name_ppid,
-                meta_str,
-                entry.message
-            );
+            print_compact_entry(&entry);
         }
     }
     
     Ok(())
 }
 
+/// One-line entry: time, [name·ppid], (repo  branch) and message
+fn print_compact_entry(entry: &LogEntry) {
+    let use_color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let icon = branch_icon(); // branch glyph (or ASCII if CLOG_ASCII is set)
+    let name_ppid = format_name_ppid(entry.name.as_deref(), entry.ppid, use_color);
+
+    // Repo (or, outside git, project) basename if available, truncate to 20 chars
+    let repo_name = entry
+        .repo_root
+        .as_deref()
+        .or(entry.project_root.as_deref())
+        .and_then(|r| Path::new(r).file_name())
+        .and_then(|os| os.to_str())
+        .map(|s| truncate_ellipsize(s, 20));
+
+    // Branch name (if available), truncate to 20 chars; `*` marks uncommitted changes
+    let dirty = if entry.is_dirty() { "*" } else { "" };
+    let branch_name = entry
+        .repo_branch
+        .as_deref()
+        .map(|s| format!("{}{}", truncate_ellipsize(s, 20), dirty));
+
+    // Linked worktree (if not the main checkout): "repo [worktree]"
+    let repo_name = match (repo_name, entry.repo_worktree.as_deref()) {
+        (Some(r), Some(wt)) if Some(wt) != entry.repo_root.as_deref() => {
+            let wt_name = Path::new(wt).file_name().and_then(|os| os.to_str()).unwrap_or(wt);
+            Some(format!("{} [{}]", r, truncate_ellipsize(wt_name, 20)))
+        }
+        (r, _) => r,
+    };
+
+    // Build compact inline repo/branch segment: " (repo  branch)"
+    let meta_str = match (repo_name.as_deref(), branch_name.as_deref()) {
+        (Some(r), Some(b)) => format!(" ({} {} {})", colorize(r, "1;32", use_color), colorize(icon, "35", use_color), colorize(b, "35", use_color)),
+        (Some(r), None) => format!(" ({}{})", colorize(r, "1;32", use_color), colorize(dirty, "35", use_color)),
+        (None, Some(b)) => format!(" ({} {})", colorize(icon, "35", use_color), colorize(b, "35", use_color)),
+        (None, None) => String::new(),
+    };
+
+    // Hook events carry their kind: "commit: abc1234 Fix parser"
+    let kind_str = entry.kind.as_deref()
+        .map(|k| format!("{} ", colorize(&format!("{}:", k), "33", use_color)))
+        .unwrap_or_default();
+
+    println!(
+        "{} {}{} {}{}",
+        colorize(&entry.timestamp.format("%H:%M:%S").to_string(), "90", use_color),
+        name_ppid,
+        meta_str,
+        kind_str,
+        entry.message
+    );
+}
+
+/// Listing filters from the command line
+fn entry_filter(db: &Database, config: &Config, args: &Args) -> Result<EntryFilter, Box<dyn std::error::Error>> {
+    let session_id = if args.session {
+        // For session filtering, try to get PID but don't warn if it fails
+        let named = session::named_session(args.as_name.as_deref());
+        current_session(db, config, named.as_ref(), false)?.1.map(|s| s.session_id)
+    } else {
+        None
+    };
+
+    let worktree = match &args.worktree {
+        Some(path) => Some(
+            git::detect_layout(Path::new(path), config.git.use_cli)
+                .map(|l| l.worktree)
+                .ok_or_else(|| format!("Not inside a git worktree: {}", path))?,
+        ),
+        None => None,
+    };
+
+    let commit_prefix = match &args.commit {
+        // Hex for git and hg, k-z letters for jj change ids
+        Some(sha) if !sha.is_empty() && sha.chars().all(|c| c.is_ascii_alphanumeric()) => Some(sha.to_lowercase()),
+        Some(sha) => return Err(format!("Invalid commit prefix: {}", sha).into()),
+        None => None,
+    };
+
+    let repo = repo_scope(config, args);
+    // Outside git, fall back to the project the directory belongs to
+    let project = match (&repo, args.all) {
+        (None, false) => env::current_dir().ok()
+            .and_then(|cwd| project::detect_project_root(&cwd))
+            .map(|p| p.to_string_lossy().to_string()),
+        _ => None,
+    };
+
+    let mut filter = EntryFilter {
+        repo,
+        project,
+        name: args.filter.clone(),
+        today: args.today,
+        since: None,
+        session_id,
+        worktree,
+        branch: args.branch.clone(),
+        commit_prefix,
+        commits: None,
+        notes_only: false,
+        path: args.path.clone(),
+    };
+
+    // The config default only applies to the current repo; --reachable always does
+    let default_scope = !args.all && args.repo.is_none();
+    if args.reachable || (config.list.reachable && default_scope && !args.no_reachable) {
+        // Reachable from the HEAD of the repo being listed, which --repo
+        // only names when it resolves to a checkout on this machine
+        let head_dir = match &args.repo {
+            Some(repo) => filter.repo.as_ref()
+                .and_then(|scope| scope.root.as_deref())
+                .map(PathBuf::from)
+                .filter(|root| root.is_dir())
+                .ok_or_else(|| format!("--reachable needs a local checkout; {} isn't one", repo))?,
+            None => env::current_dir()?,
+        };
+        let candidates = db.distinct_commits(&filter)?;
+        match git::reachable_from_head(&head_dir, &candidates) {
+            Some(reachable) => filter.commits = Some(reachable),
+            None if args.reachable => return Err("--reachable needs a git repository with commits".into()),
+            None => {}
+        }
+    }
+
+    Ok(filter)
+}
+
+/// Repository a listing is scoped to: `--repo` if given, else the current
+/// repo (unless `--all`)
+fn repo_scope(config: &Config, args: &Args) -> Option<RepoScope> {
+    if let Some(repo) = &args.repo {
+        return Some(resolve_repo_arg(config, repo));
+    }
+    if args.all {
+        return None;
+    }
+    env::current_dir().ok()
+        .and_then(|cwd| vcs::detect_repo_scope(&cwd, config.git.use_cli))
+}
+
+/// `--repo` accepts an alias from the config, a path to a checkout, a remote
+/// URL or a repo id (or a root path recorded on another machine)
+fn resolve_repo_arg(config: &Config, arg: &str) -> RepoScope {
+    let target = config.git.repo_aliases.get(arg).map(String::as_str).unwrap_or(arg);
+
+    let path = Path::new(target);
+    if path.is_dir() {
+        if let Some(scope) = vcs::detect_repo_scope(path, config.git.use_cli) {
+            return scope;
+        }
+    }
+    if git::looks_like_remote(target) {
+        return RepoScope { id: Some(git::normalize_remote_url(target)), root: None, worktrees: Vec::new() };
+    }
+    RepoScope { id: Some(target.to_string()), root: Some(target.to_string()), worktrees: Vec::new() }
+}
+
 fn shorten_path(path: &str) -> String {
     if let Some(home) = dirs::home_dir() {
         let home_str = home.to_string_lossy();
@@ -272,66 +1084,19 @@ fn shorten_path(path: &str) -> String {
     path.to_string()
 }
 
-fn handle_stream_entries(db: &Database, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+fn handle_stream_entries(db: &Database, config: &Config, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
     // Determine filters (respect current repo by default, unless --all or --repo provided)
-    let current_repo = if !args.all && args.repo.is_none() {
-        env::current_dir().ok()
-            .and_then(|cwd| git::detect_repo_info(&cwd))
-            .map(|info| info.root)
-    } else {
-        None
-    };
-
-    let repo_filter = args.repo.as_deref().or(current_repo.as_deref());
-
-    let session_id = if args.session {
-        let ppid = session::get_ppid().unwrap_or_else(|| process::id());
-        db.get_active_session(ppid)?.map(|s| s.session_id)
-    } else {
-        None
-    };
+    let filter = entry_filter(db, config, args)?;
 
     // Initial fetch: last 10 entries
-    let mut entries = db.list_entries(
-        10,
-        repo_filter,
-        args.filter.as_deref(),
-        args.today,
-        session_id.as_deref()
-    )?;
+    let mut entries = db.list_entries(10, &filter)?;
     entries.reverse();
 
     // Print initial entries in compact format
     let mut last_received: Option<chrono::DateTime<chrono::Utc>> = None;
     for entry in entries {
         if let Some(ts) = entry.received_at { last_received = Some(last_received.map(|lr| lr.max(ts)).unwrap_or(ts)); }
-        let use_color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
-        let icon = branch_icon();
-        let name_ppid = format_name_ppid(entry.name.as_deref(), entry.ppid, use_color);
-        let repo_name = entry
-            .repo_root
-            .as_deref()
-            .and_then(|r| Path::new(r).file_name())
-            .and_then(|os| os.to_str())
-            .map(|s| truncate_ellipsize(s, 20));
-        let branch_name = entry
-            .repo_branch
-            .as_deref()
-            .map(|s| truncate_ellipsize(s, 20));
-        let meta_str = match (repo_name.as_deref(), branch_name.as_deref()) {
-            (Some(r), Some(b)) => format!(" ({} {} {})", colorize(r, "1;32", use_color), colorize(icon, "35", use_color), colorize(b, "35", use_color)),
-            (Some(r), None) => format!(" ({})", colorize(r, "1;32", use_color)),
-            (None, Some(b)) => format!(" ({} {})", colorize(icon, "35", use_color), colorize(b, "35", use_color)),
-            (None, None) => String::new(),
-        };
-
-        println!(
-            "{} {}{} {}",
-            colorize(&entry.timestamp.format("%H:%M:%S").to_string(), "90", use_color),
-            name_ppid,
-            meta_str,
-            entry.message
-        );
+        print_compact_entry(&entry);
     }
 
     // Setup Ctrl+C handler
@@ -345,51 +1110,343 @@ fn handle_stream_entries(db: &Database, args: &Args) -> Result<(), Box<dyn std::
 
     // Poll loop
     while running.load(Ordering::SeqCst) {
-        let since = last_received.unwrap_or_else(|| Utc::now());
-        let new_entries = db.list_entries_received_after(
-            since,
-            repo_filter,
-            args.filter.as_deref(),
-            args.today,
-            session_id.as_deref(),
-        )?;
+        let since = last_received.unwrap_or_else(Utc::now);
+        let new_entries = db.list_entries_received_after(since, &filter)?;
 
         if !new_entries.is_empty() {
             for entry in &new_entries {
                 if let Some(ts) = entry.received_at { last_received = Some(last_received.map(|lr| lr.max(ts)).unwrap_or(ts)); }
-                let use_color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
-                let icon = branch_icon();
-                let name_ppid = format_name_ppid(entry.name.as_deref(), entry.ppid, use_color);
-                let repo_name = entry
-                    .repo_root
-                    .as_deref()
-                    .and_then(|r| Path::new(r).file_name())
-                    .and_then(|os| os.to_str())
-                    .map(|s| truncate_ellipsize(s, 20));
-                let branch_name = entry
-                    .repo_branch
-                    .as_deref()
-                    .map(|s| truncate_ellipsize(s, 20));
-                let meta_str = match (repo_name.as_deref(), branch_name.as_deref()) {
-                    (Some(r), Some(b)) => format!(" ({} {} {})", colorize(r, "1;32", use_color), colorize(icon, "35", use_color), colorize(b, "35", use_color)),
-                    (Some(r), None) => format!(" ({})", colorize(r, "1;32", use_color)),
-                    (None, Some(b)) => format!(" ({} {})", colorize(icon, "35", use_color), colorize(b, "35", use_color)),
-                    (None, None) => String::new(),
+                print_compact_entry(entry);
+            }
+        }
+
+        thread::sleep(Duration::from_millis(500));
+    }
+
+    Ok(())
+}
+
+/// Entries whose commit falls in `range`, across every clone of the current repo
+fn handle_changelog(db: &Database, config: &Config, range: &str, group_by: changelog::GroupBy, template: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let cwd = env::current_dir()?;
+    let scope = git::detect_repo_scope(&cwd, config.git.use_cli)
+        .ok_or("clog changelog must be run inside a git repository")?;
+    let template = match template {
+        Some(path) => {
+            let source = std::fs::read_to_string(path)
+                .map_err(|e| format!("Could not read template {}: {}", path.display(), e))?;
+            changelog::Template::parse(&source)?
+        }
+        None => changelog::Template::default(),
+    };
+
+    let commits = git::commits_in_range(&cwd, range)?;
+    let filter = EntryFilter {
+        repo: Some(scope),
+        commits: Some(commits.iter().map(|c| c.sha.clone()).collect()),
+        notes_only: true,
+        ..Default::default()
+    };
+    let entries = db.entries_matching(&filter)?;
+
+    print!("{}", changelog::render(range, &commits, &entries, group_by, &template));
+    Ok(())
+}
+
+/// Print a commit message drafted from the entries logged at HEAD, or (as
+/// the prepare-commit-msg hook) write it into git's message file
+fn handle_commit_msg(db: &Database, config: &Config, hook: Option<&[String]>) -> Result<(), Box<dyn std::error::Error>> {
+    let cwd = env::current_dir()?;
+    let source = hook.and_then(|h| h.get(1)).map(String::as_str);
+    if hook.is_some() && !commit_msg::wants_trailer(source) {
+        return Ok(());
+    }
+
+    let scope = git::detect_repo_scope(&cwd, config.git.use_cli)
+        .ok_or("clog commit-msg must be run inside a git repository")?;
+    // By root path rather than repo id: only this device's entries, never a
+    // teammate's logged at the same HEAD on a shared database
+    let scope = RepoScope { id: None, ..scope };
+    let entries = match git::head_commit(&cwd, config.git.use_cli) {
+        Some(head) => db.entries_matching(&EntryFilter {
+            repo: Some(scope),
+            commits: Some(vec![head]),
+            notes_only: true,
+            ..Default::default()
+        })?,
+        None => Vec::new(),
+    };
+
+    let Some(file) = hook.and_then(|h| h.first()) else {
+        let draft = commit_msg::draft(&entries).ok_or("No entries logged since the last commit")?;
+        print!("{}", draft);
+        if let Some(trailer) = commit_msg::trailer(&entries) {
+            println!("\n{}", trailer);
+        }
+        return Ok(());
+    };
+
+    let file = Path::new(file);
+    if commit_msg::wants_draft(source) {
+        if let Some(draft) = commit_msg::draft(&entries) {
+            commit_msg::prefill(file, &draft)?;
+        }
+    }
+    if let Some(trailer) = commit_msg::trailer(&entries) {
+        git::set_trailer(&cwd, file, &trailer)?;
+    }
+    Ok(())
+}
+
+/// Commits on local branches interleaved with the repo's entries, with a
+/// marker wherever the branch or commit changed between entries
+fn handle_timeline(db: &Database, config: &Config, days: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let cwd = env::current_dir()?;
+    let scope = git::detect_repo_scope(&cwd, config.git.use_cli)
+        .ok_or("clog timeline must be run inside a git repository")?;
+    let since = Utc::now() - chrono::Duration::days(days as i64);
+
+    let commits = match git::head_commit(&cwd, config.git.use_cli) {
+        Some(_) => git::commits_since(&cwd, since)?,
+        None => Vec::new(),
+    };
+    let entries = db.entries_matching(&EntryFilter { repo: Some(scope), since: Some(since), ..Default::default() })?;
+
+    let use_color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let ascii = env::var_os("CLOG_ASCII").is_some();
+    let mut day = None;
+    for item in timeline::build(commits, entries) {
+        let date = match &item {
+            timeline::Item::Commit(c) => Some(c.author_time.date_naive()),
+            timeline::Item::Entry(e) => Some(e.timestamp.date_naive()),
+            timeline::Item::Moved { .. } => None,
+        };
+        if let Some(d) = date.filter(|d| day != Some(*d)) {
+            println!("{}", colorize(&format!("── {} ──", d.format("%Y-%m-%d")), "1", use_color));
+            day = Some(d);
+        }
+
+        match item {
+            timeline::Item::Commit(c) => println!(
+                "{} {} {} {}",
+                colorize(&c.author_time.format("%H:%M:%S").to_string(), "90", use_color),
+                colorize(if ascii { "*" } else { "●" }, "33", use_color),
+                colorize(&c.short, "33", use_color),
+                c.subject
+            ),
+            timeline::Item::Entry(e) => print_compact_entry(&e),
+            timeline::Item::Moved { branch, commit } => {
+                let arrow = if ascii { "->" } else { "→" };
+                let short = |sha: &str| sha[..7.min(sha.len())].to_string();
+                let name = |b: Option<String>| b.unwrap_or_else(|| "(detached)".to_string());
+                let commit = commit.map(|(from, to)| format!("{} {} {}", short(&from), arrow, short(&to)));
+                let what = match (branch, commit) {
+                    (Some((from, to)), Some(c)) => format!("{} {} {} ({})", name(from), arrow, name(to), c),
+                    (Some((from, to)), None) => format!("{} {} {}", name(from), arrow, name(to)),
+                    (None, Some(c)) => c,
+                    (None, None) => continue,
                 };
+                println!("         {}", colorize(&format!("{} {}", if ascii { "|" } else { "↳" }, what), "35", use_color));
+            }
+        }
+    }
+    Ok(())
+}
 
-                println!(
-                    "{} {}{} {}",
-                    colorize(&entry.timestamp.format("%H:%M:%S").to_string(), "90", use_color),
-                    name_ppid,
-                    meta_str,
-                    entry.message
-                );
+/// Find commits recorded on entries that nothing reaches any more and remap
+/// them to their rewritten equivalents by patch id. The post-rewrite hook
+/// does this exactly as rewrites happen; this catches the rest.
+fn handle_remap(db: &Database, config: &Config, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let cwd = env::current_dir()?;
+    let scope = git::detect_repo_scope(&cwd, config.git.use_cli)
+        .ok_or("clog remap must be run inside a git repository")?;
+    let commits = db.distinct_commits(&EntryFilter { repo: Some(scope.clone()), ..Default::default() })?;
+    let orphaned = git::unreachable_commits(&cwd, &commits);
+    let pairs = git::match_by_patch_id(&cwd, &orphaned)?;
+
+    if dry_run {
+        for (old, new) in &pairs {
+            let subject = git::commit_info(&cwd, new).map(|c| c.subject).unwrap_or_default();
+            println!("{} → {} {}", &old[..7.min(old.len())], &new[..7.min(new.len())], subject);
+        }
+    } else {
+        let updated = db.remap_commits(&scope, &pairs)?;
+        println!("Remapped {} {} from {} rewritten {}",
+            updated, if updated == 1 { "entry" } else { "entries" },
+            pairs.len(), if pairs.len() == 1 { "commit" } else { "commits" });
+    }
+    let unmatched = orphaned.len() - pairs.len();
+    if unmatched > 0 {
+        println!("  {} unreachable {} had no match", unmatched, if unmatched == 1 { "commit" } else { "commits" });
+    }
+    Ok(())
+}
+
+/// Entries that captured `file`, plus those logged at the parent of a commit
+/// that touched it or named in such a commit's trailer
+fn handle_blame(db: &Database, config: &Config, file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let cwd = env::current_dir()?;
+    let scope = git::detect_repo_scope(&cwd, config.git.use_cli)
+        .ok_or("clog blame must be run inside a git repository")?;
+    let path = git::worktree_path(&cwd, file)
+        .ok_or_else(|| format!("{} is not inside this worktree", file.display()))?;
+
+    let commits = match git::head_commit(&cwd, config.git.use_cli) {
+        Some(_) => git::file_commits(&cwd, &path)?,
+        None => Vec::new(),
+    };
+    let parents: Vec<String> = commits.iter().filter_map(|c| c.parent.clone()).collect();
+    let event_ids: Vec<String> = commits.iter().flat_map(|c| c.entries.clone()).collect();
+    let entries = db.entries_for_file(&scope, &path, &parents, &event_ids)?;
+
+    if entries.is_empty() {
+        println!("No entries linked to {}", path);
+        return Ok(());
+    }
+    let use_color = std::io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    for (entry, direct) in &entries {
+        print_compact_entry(entry);
+        if *direct {
+            continue;
+        }
+        // Linked through a commit: name the one that touched the file
+        let via = commits.iter().find(|c| {
+            entry.event_id.as_ref().is_some_and(|id| c.entries.contains(id))
+                || (c.parent.is_some() && c.parent == entry.repo_commit)
+        });
+        if let Some(c) = via {
+            println!("         {}", colorize(&format!("via {} {}", c.commit.short, c.commit.subject), "90", use_color));
+        }
+    }
+    Ok(())
+}
+
+/// Attach entries to their commits as notes, merging with notes already there
+fn handle_notes_export(db: &Database, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let cwd = env::current_dir()?;
+    let scope = git::detect_repo_scope(&cwd, config.git.use_cli)
+        .ok_or("clog notes must be run inside a git repository")?;
+    let entries = db.entries_matching(&EntryFilter { repo: Some(scope), ..Default::default() })?;
+
+    let mut by_commit: std::collections::BTreeMap<String, Vec<LogEntry>> = Default::default();
+    for entry in entries {
+        if let Some(commit) = entry.repo_commit.clone() {
+            by_commit.entry(commit).or_default().push(entry);
+        }
+    }
+    let commits: Vec<String> = by_commit.keys().cloned().collect();
+    let present = git::existing_commits(&cwd, &commits)?;
+
+    let (mut exported, mut noted) = (0, 0);
+    for commit in &present {
+        let existing = git::read_note(&cwd, notes::NOTES_REF, commit);
+        let (note, added) = notes::merge(existing.as_deref(), &by_commit[commit]);
+        if added > 0 {
+            git::write_note(&cwd, notes::NOTES_REF, commit, &note)?;
+            exported += added;
+            noted += 1;
+        }
+    }
+
+    println!("Exported {} {} to {} {} (refs/notes/{})",
+        exported, if exported == 1 { "entry" } else { "entries" },
+        noted, if noted == 1 { "commit" } else { "commits" },
+        notes::NOTES_REF);
+    let missing = commits.len() - present.len();
+    if missing > 0 {
+        println!("  Skipped {} {} not in this clone", missing, if missing == 1 { "commit" } else { "commits" });
+    }
+    Ok(())
+}
+
+/// Load entries from notes, skipping event ids the database already has.
+/// Imported entries take this checkout's paths and repo id.
+fn handle_notes_import(db: &Database, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let cwd = env::current_dir()?;
+    let layout = git::detect_layout(&cwd, config.git.use_cli)
+        .ok_or("clog notes must be run inside a git repository")?;
+    let mut base = LogEntry {
+        directory: layout.worktree.clone(),
+        vcs: Some("git".to_string()),
+        repo_root: Some(layout.root),
+        repo_worktree: Some(layout.worktree),
+        repo_superproject: layout.superproject,
+        repo_id: git::repo_id(&cwd, config.git.use_cli),
+        ..Default::default()
+    };
+
+    let mut entries = Vec::new();
+    for commit in git::noted_commits(&cwd, notes::NOTES_REF)? {
+        let Some(note) = git::read_note(&cwd, notes::NOTES_REF, &commit) else { continue };
+        base.repo_commit = Some(commit);
+        entries.extend(notes::parse(&note, &base));
+    }
+    let imported = db.insert_log_entries(&entries)?;
+
+    println!("Imported {} new {} ({} already present)",
+        imported, if imported == 1 { "entry" } else { "entries" },
+        entries.len() as u64 - imported);
+    Ok(())
+}
+
+fn handle_hook_install(names: &[&str], install: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let cwd = env::current_dir()?;
+    for name in names {
+        if install {
+            println!("Installed {}", hooks::install(&cwd, name)?.display());
+        } else {
+            match hooks::uninstall(&cwd, name)? {
+                Some(path) => println!("Removed {}", path.display()),
+                None => println!("No {} hook installed", name),
             }
         }
+    }
+    Ok(())
+}
 
-        thread::sleep(Duration::from_millis(500));
+fn handle_session_explain(config: &Config, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(named) = session::named_session(args.as_name.as_deref()) {
+        println!("Explicit session '{}' (from --as/CLOG_SESSION/CLOG_NAME); process tree is not consulted", named.key);
+        return Ok(());
     }
 
+    let ancestors = session::ancestry();
+    let anchor = session::resolve_anchor(&config.session.anchors, &ancestors);
+
+    println!("Process ancestry (clog PID {}):", process::id());
+    for a in &ancestors {
+        let marker = if anchor.as_ref().is_some_and(|x| x.pid == a.pid) { "*" } else { " " };
+        let started = chrono::DateTime::from_timestamp(a.start_time as i64, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_default();
+        println!("{} [{}] PID {} {} (started {})", marker, a.depth, a.pid, a.name, started);
+        if let Some(exe) = &a.exe {
+            println!("      exe: {}", exe);
+        }
+        if !a.cmdline.is_empty() {
+            println!("      cmd: {}", truncate_ellipsize(&a.cmdline, 120));
+        }
+    }
+
+    println!();
+    println!("Anchor rules ({}):", config::get_config_path().display());
+    for (i, rule) in config.session.anchors.iter().enumerate() {
+        let result = match session::rule_match(rule, &ancestors) {
+            Some(a) => format!("matches PID {} ({}) at depth {}", a.pid, a.name, a.depth),
+            None => "no match".to_string(),
+        };
+        println!("  #{} {} -> {}", i + 1, rule.describe(), result);
+    }
+
+    println!();
+    match &anchor {
+        Some(a @ Anchor { rule: Some(i), .. }) => println!("Session anchor: PID {} started {} (rule #{})", a.pid, a.start_time, i + 1),
+        Some(a) => println!("Session anchor: PID {} started {} (no rule matched, immediate parent)", a.pid, a.start_time),
+        None => println!("Session anchor: none (could not read process tree)"),
+    }
+    if let Some(a) = &anchor {
+        println!("Default name for a new session: {}", session::auto_name(a));
+    }
     Ok(())
 }
 
@@ -397,14 +1454,28 @@ fn handle_info_command() -> Result<(), Box<dyn std::error::Error>> {
     dotenv::dotenv().ok();
     let device_id = device::get_or_create_device_id()?;
     println!("Device ID: {}", device_id);
-    // Attempt to connect to Postgres and report status
+    println!("Features: {}", compiled_features().join(", "));
     match Database::new() {
-        Ok(_db) => println!("Database: Connected (PostgreSQL)"),
+        Ok(db) => println!("Database: Connected ({})", db.describe()),
         Err(e) => println!("Database: Connection failed - {}", e),
     }
     Ok(())
 }
 
+/// Cargo features this binary was built with
+fn compiled_features() -> Vec<&'static str> {
+    [
+        ("sqlite", cfg!(feature = "sqlite")),
+        ("postgres", cfg!(feature = "postgres")),
+        ("tls", cfg!(feature = "tls")),
+        ("keyring", cfg!(feature = "keyring")),
+        ("git-cli", cfg!(feature = "git-cli")),
+    ]
+    .into_iter()
+    .filter_map(|(name, on)| on.then_some(name))
+    .collect()
+}
+
 // sync/login/logout removed
 
 fn colorize(s: &str, code: &str, enable: bool) -> String {
@@ -1,17 +1,40 @@
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct LogEntry {
-    pub id: Option<i64>,
     pub ppid: u32,
     pub name: Option<String>,
     pub timestamp: DateTime<Utc>,
     pub directory: String,
     pub message: String,
     pub session_id: String,
+    /// Version control system of the repository (`git`, `hg`, `jj`)
+    pub vcs: Option<String>,
     pub repo_root: Option<String>,
     pub repo_branch: Option<String>,
     pub repo_commit: Option<String>,
+    /// Commit the entry was logged at, when `repo_commit` has since been
+    /// remapped to its rewritten equivalent
+    pub repo_commit_original: Option<String>,
+    /// jj change id of `@`; `repo_commit` is the git commit it sits on
+    pub repo_change: Option<String>,
+    pub repo_id: Option<String>,
+    pub repo_worktree: Option<String>,
+    pub repo_superproject: Option<String>,
+    pub repo_staged: Option<u32>,
+    pub repo_unstaged: Option<u32>,
+    pub repo_untracked: Option<u32>,
+    pub repo_upstream: Option<String>,
+    pub repo_ahead: Option<u32>,
+    pub repo_behind: Option<u32>,
+    pub repo_describe: Option<String>,
+    /// Project root found from marker files, the default scope outside git
+    pub project_root: Option<String>,
+    /// Event type for entries written by git hooks; `None` for notes
+    pub kind: Option<String>,
+    /// Id of the working-tree diff stored with `--snapshot`
+    pub snapshot: Option<String>,
     pub event_id: Option<String>,
     pub received_at: Option<DateTime<Utc>>, // server-side insert time (Postgres)
 }
@@ -19,16 +42,105 @@ pub struct LogEntry {
 #[derive(Debug, Clone)]
 pub struct Session {
     pub session_id: String,
-    pub ppid: u32,
     pub name: Option<String>,
-    pub first_seen: DateTime<Utc>,
-    pub last_seen: DateTime<Utc>,
-    pub is_active: bool,
+}
+
+/// How a write finds the active session it belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SessionLookup {
+    /// The session anchored at this exact process (PID and start time)
+    Anchor { ppid: u32, start_time: u64 },
+    /// The session registered under a key; with a name, only while the
+    /// session is still called that (a rename takes the full path)
+    Key { key: String, name: Option<String> },
+}
+
+impl LogEntry {
+    /// Logged on top of staged or unstaged changes (untracked files don't count)
+    pub fn is_dirty(&self) -> bool {
+        self.repo_staged.unwrap_or(0) + self.repo_unstaged.unwrap_or(0) > 0
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct RepoInfo {
+    /// Main checkout of the repository (shared by all its linked worktrees)
     pub root: String,
+    /// Top level of the worktree the command ran in; equals `root` outside linked worktrees
+    pub worktree: String,
+    /// Working tree of the superproject when inside a submodule
+    pub superproject: Option<String>,
+    /// Branch, or the active bookmark for hg and jj
     pub branch: Option<String>,
+    /// Commit id; for jj the git commit `@` sits on (git's HEAD in a colocated repo)
     pub commit: String,
+    /// Change id of `@`, for jj only
+    pub change: Option<String>,
+    /// Working-tree counts; `None` when `git status` couldn't be run
+    pub staged: Option<u32>,
+    pub unstaged: Option<u32>,
+    pub untracked: Option<u32>,
+    pub upstream: Option<String>,
+    pub ahead: Option<u32>,
+    pub behind: Option<u32>,
+    /// Nearest tag, as reported by `git describe --tags`
+    pub describe: Option<String>,
+    /// Modified or staged paths, relative to the worktree (untracked files excluded)
+    pub files: Vec<String>,
+    /// Stable identity across clones (see `git::repo_id`)
+    pub id: Option<String>,
+}
+
+/// Which repository a listing is scoped to: entries with this repo id, or
+/// (for entries logged before ids existed) this root path
+#[derive(Debug, Clone)]
+pub struct RepoScope {
+    pub id: Option<String>,
+    pub root: Option<String>,
+    /// Linked worktrees of `root`: entries logged there before worktrees
+    /// were told apart recorded the worktree as their root
+    pub worktrees: Vec<String>,
+}
+
+/// Listing filters shared by `--list` and `--stream`
+#[derive(Debug, Clone, Default)]
+pub struct EntryFilter {
+    pub repo: Option<RepoScope>,
+    /// Only entries of this project (the default scope outside git)
+    pub project: Option<String>,
+    pub name: Option<String>,
+    pub today: bool,
+    /// Only entries logged at or after this time
+    pub since: Option<DateTime<Utc>>,
+    pub session_id: Option<String>,
+    /// Only entries logged in this worktree
+    pub worktree: Option<String>,
+    pub branch: Option<String>,
+    /// Hex prefix of `repo_commit`
+    pub commit_prefix: Option<String>,
+    /// Only entries whose `repo_commit` is one of these (e.g. reachable from HEAD)
+    pub commits: Option<Vec<String>>,
+    /// Leave out entries written by git hooks
+    pub notes_only: bool,
+    /// Only entries with a captured file matching this glob
+    pub path: Option<String>,
+}
+
+/// A commit as listed by `git log`
+#[derive(Debug, Clone)]
+pub struct Commit {
+    pub sha: String,
+    pub short: String,
+    pub subject: String,
+    pub author_time: DateTime<Utc>,
+}
+
+/// A commit that touched a file, and what links entries to it
+#[derive(Debug, Clone)]
+pub struct FileCommit {
+    pub commit: Commit,
+    /// First parent: entries logged there led up to this commit
+    pub parent: Option<String>,
+    /// Event ids from the commit's `Clog-Entries` trailers
+    pub entries: Vec<String>,
 }
@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use crate::models::LogEntry;
+
+/// Entries travel under `refs/notes/clog`
+pub const NOTES_REF: &str = "clog";
+
+/// One entry as stored in a note: a JSON object per line. Lines start with the
+/// event id, so sorting them (as `git notes merge -s cat_sort_uniq` does)
+/// keeps them in logging order.
+#[derive(Debug, Serialize, Deserialize)]
+struct NoteLine {
+    event_id: String,
+    timestamp: String,
+    name: Option<String>,
+    session_id: String,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    branch: Option<String>,
+}
+
+/// Add `entries` to a commit's existing note. The existing note is kept
+/// verbatim, lines clog didn't write included; entries it already holds (by
+/// event id) are skipped and the rest are appended in event id order.
+/// Returns the new note and how many entries it gained.
+pub fn merge(existing: Option<&str>, entries: &[LogEntry]) -> (String, usize) {
+    let existing = existing.unwrap_or("");
+    let mut known: HashSet<String> = existing
+        .lines()
+        .filter_map(|l| serde_json::from_str::<NoteLine>(l).ok())
+        .map(|n| n.event_id)
+        .collect();
+
+    let mut added = Vec::new();
+    for entry in entries {
+        let Some(event_id) = &entry.event_id else { continue };
+        if !known.insert(event_id.clone()) {
+            continue;
+        }
+        let line = NoteLine {
+            event_id: event_id.clone(),
+            timestamp: entry.timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+            name: entry.name.clone(),
+            session_id: entry.session_id.clone(),
+            message: entry.message.clone(),
+            kind: entry.kind.clone(),
+            branch: entry.repo_branch.clone(),
+        };
+        added.push(serde_json::to_string(&line).expect("note line serializes"));
+    }
+    added.sort();
+
+    let mut note = existing.to_string();
+    if !note.is_empty() && !note.ends_with('\n') && !added.is_empty() {
+        note.push('\n');
+    }
+    for line in &added {
+        note.push_str(line);
+        note.push('\n');
+    }
+    (note, added.len())
+}
+
+/// Entries stored in a note, filled in from `base` (the commit and the
+/// local repository context). Lines that don't parse are skipped.
+pub fn parse(note: &str, base: &LogEntry) -> Vec<LogEntry> {
+    note.lines()
+        .filter_map(|l| serde_json::from_str::<NoteLine>(l).ok())
+        .filter_map(|n| {
+            let timestamp = DateTime::parse_from_rfc3339(&n.timestamp).ok()?.with_timezone(&Utc);
+            Some(LogEntry {
+                name: n.name,
+                timestamp,
+                message: n.message,
+                session_id: n.session_id,
+                repo_branch: n.branch,
+                kind: n.kind,
+                event_id: Some(n.event_id),
+                ..base.clone()
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(event_id: &str, message: &str) -> LogEntry {
+        LogEntry {
+            event_id: Some(event_id.to_string()),
+            message: message.to_string(),
+            session_id: "s".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_keeps_hand_written_lines() {
+        let (first, added) = merge(None, &[entry("01B", "second"), entry("01A", "first")]);
+        assert_eq!(added, 2);
+        let ours: Vec<&str> = first.lines().collect();
+
+        // Someone else's notes, out of order and repeated, around clog's lines
+        let existing = format!("zebra: reviewed\n{}\nzebra: reviewed\napple\n{}", ours[1], ours[0]);
+        let (note, added) = merge(Some(&existing), &[entry("01A", "first"), entry("01C", "third"), entry("01C", "third")]);
+        assert_eq!(added, 1);
+        let lines: Vec<&str> = note.lines().collect();
+        assert_eq!(&lines[..5], ["zebra: reviewed", ours[1], "zebra: reviewed", "apple", ours[0]]);
+        assert_eq!(lines.len(), 6);
+        assert!(lines[5].contains("\"third\""));
+
+        let parsed = parse(&note, &LogEntry::default());
+        assert_eq!(parsed.iter().map(|e| e.message.as_str()).collect::<Vec<_>>(), ["second", "first", "third"]);
+        assert_eq!(merge(Some(&note), &[entry("01B", "second")]), (note.clone(), 0));
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use dirs::home_dir;
+
+/// Explicit project marker; an empty file is enough
+const PROJECT_MARKER: &str = ".clog-project";
+
+/// Manifests that mark a project root when no `.clog-project` is found
+const MANIFESTS: [&str; 3] = ["Cargo.toml", "package.json", "pyproject.toml"];
+
+/// Root of the project containing `cwd`, for scoping entries outside git.
+///
+/// The nearest `.clog-project` wins. Otherwise the nearest directory with a
+/// manifest; for Cargo.toml the enclosing workspace root, if any, so every
+/// crate of a workspace shares one scope. Manifests in the home directory or
+/// above it don't count.
+pub fn detect_project_root(cwd: &Path) -> Option<PathBuf> {
+    detect_project_root_in(cwd, home_dir().as_deref())
+}
+
+fn detect_project_root_in(cwd: &Path, home: Option<&Path>) -> Option<PathBuf> {
+    let cwd = cwd.canonicalize().ok()?;
+    if let Some(dir) = cwd.ancestors().find(|d| d.join(PROJECT_MARKER).is_file()) {
+        return Some(dir.to_path_buf());
+    }
+
+    let home = home.and_then(|h| h.canonicalize().ok());
+    let below_home = |d: &Path| home.as_deref().is_none_or(|h| !h.starts_with(d));
+    let nearest = cwd
+        .ancestors()
+        .take_while(|d| below_home(d))
+        .find(|d| MANIFESTS.iter().any(|m| d.join(m).is_file()))?;
+
+    if nearest.join("Cargo.toml").is_file() {
+        let workspace = nearest
+            .ancestors()
+            .take_while(|d| below_home(d))
+            .find(|d| is_cargo_workspace(&d.join("Cargo.toml")));
+        if let Some(root) = workspace {
+            return Some(root.to_path_buf());
+        }
+    }
+    Some(nearest.to_path_buf())
+}
+
+/// A Cargo.toml with a `[workspace]` table
+fn is_cargo_workspace(manifest: &Path) -> bool {
+    fs::read_to_string(manifest).is_ok_and(|s| s.lines().any(|l| l.trim() == "[workspace]"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marker_then_nearest_manifest_then_workspace() {
+        let home = std::env::temp_dir().join(format!("clog-project-{}", ulid::Ulid::new()));
+        let files = [
+            "Cargo.toml",
+            "ws/Cargo.toml",
+            "ws/crates/a/Cargo.toml",
+            "solo/Cargo.toml",
+            "app/package.json",
+            "app/py/pyproject.toml",
+            "marked/.clog-project",
+            "marked/pkg/package.json",
+        ];
+        for file in files {
+            let path = home.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, if file == "ws/Cargo.toml" { "[workspace]\nmembers = [\"crates/*\"]\n" } else { "" }).unwrap();
+        }
+        for dir in ["ws/crates/a/src", "app/py/pkg", "marked/pkg/lib", "loose/dir"] {
+            fs::create_dir_all(home.join(dir)).unwrap();
+        }
+        let home = home.canonicalize().unwrap();
+        let root = |dir: &str| detect_project_root_in(&home.join(dir), Some(&home));
+
+        // Crates of a workspace share the workspace root; other crates are their own
+        assert_eq!(root("ws/crates/a/src"), Some(home.join("ws")));
+        assert_eq!(root("solo"), Some(home.join("solo")));
+        // The nearest manifest wins over ones further up
+        assert_eq!(root("app/py/pkg"), Some(home.join("app/py")));
+        assert_eq!(root("app"), Some(home.join("app")));
+        // .clog-project wins over a nearer manifest
+        assert_eq!(root("marked/pkg/lib"), Some(home.join("marked")));
+        // The manifest in the home directory itself doesn't make a project
+        assert_eq!(root("loose/dir"), None);
+        assert_eq!(root("."), None);
+
+        fs::remove_dir_all(&home).unwrap();
+    }
+}
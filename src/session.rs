@@ -1,68 +1,381 @@
+#[cfg(not(target_os = "linux"))]
 use sysinfo::System;
+use crate::config::AnchorRule;
+use serde::{Deserialize, Serialize};
+use std::env;
 use std::process;
 
-/// Find the stable parent process (Claude Code, Codex, Gemini, or shell)
-/// by climbing up the process tree
-pub fn get_ppid() -> Option<u32> {
-    let pid = process::id();
-    let system = System::new_all();
-    
-    let mut current_pid = sysinfo::Pid::from_u32(pid);
-    
-    // Debug: print process tree
-    if std::env::var("CLOG_DEBUG").is_ok() {
-        eprintln!("Current PID: {}", pid);
-        let mut debug_pid = current_pid;
-        for i in 0..5 {
-            if let Some(proc) = system.process(debug_pid) {
-                eprintln!("  [{}] PID {} - {}", i, debug_pid.as_u32(), proc.name().to_string_lossy());
-                if let Some(parent) = proc.parent() {
-                    debug_pid = parent;
-                } else {
-                    break;
-                }
-            }
-        }
+/// Explicit session identity supplied via `--as`, `CLOG_SESSION` or `CLOG_NAME`.
+/// When present, process-tree detection is skipped entirely.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamedSession {
+    pub key: String,
+    /// Name requested for this invocation; `None` keeps whatever the session
+    /// is already called (or the key, for a new session)
+    pub name: Option<String>,
+}
+
+/// Resolve an explicit session identity for this invocation, if any.
+///
+/// `CLOG_SESSION` sets the key used to find the session; the display name comes
+/// from `--as`, then `CLOG_NAME`. Without `CLOG_SESSION` the name doubles as the key.
+pub fn named_session(as_name: Option<&str>) -> Option<NamedSession> {
+    let key = env_value("CLOG_SESSION");
+    let name = as_name
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty())
+        .or_else(|| env_value("CLOG_NAME"));
+
+    match (key, name) {
+        (Some(key), name) => Some(NamedSession { key, name }),
+        (None, Some(name)) => Some(NamedSession { key: name.clone(), name: Some(name) }),
+        (None, None) => None,
+    }
+}
+
+fn env_value(var: &str) -> Option<String> {
+    env::var(var)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Immediate parent PID without scanning the process table
+pub fn immediate_parent() -> u32 {
+    #[cfg(unix)]
+    {
+        std::os::unix::process::parent_id()
+    }
+    #[cfg(not(unix))]
+    {
+        process::id()
     }
-    
-    // First pass: look for AI assistants (node/codex/gemini/claude)
-    let mut check_pid = current_pid;
-    for _ in 0..20 {
-        let process = system.process(check_pid)?;
-        let parent_pid = process.parent()?;
-        let parent_process = system.process(parent_pid)?;
-        
-        let name = parent_process.name().to_string_lossy().to_lowercase();
-        
-        // Check for AI assistant processes
-        if name.contains("node") ||     // Claude Code runs in node
-           name.contains("claude") || 
-           name.contains("codex") || 
-           name.contains("gemini") {
-            return Some(parent_pid.as_u32());
+}
+
+/// A process above clog in the process tree
+#[derive(Debug, Clone)]
+pub struct Ancestor {
+    pub depth: usize,
+    pub pid: u32,
+    /// Process start time (seconds since the epoch)
+    pub start_time: u64,
+    pub name: String,
+    pub exe: Option<String>,
+    pub cmdline: String,
+}
+
+impl Ancestor {
+    fn to_anchor(&self, rule: Option<usize>) -> Anchor {
+        Anchor { pid: self.pid, start_time: self.start_time, name: self.name.clone(), rule }
+    }
+}
+
+/// The chosen anchor and the index of the rule that selected it
+/// (`None` when falling back to the immediate parent).
+///
+/// PID plus start time identifies the process exactly: a recycled PID has a
+/// different start time, so it never inherits another process's session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Anchor {
+    pub pid: u32,
+    pub start_time: u64,
+    /// Process name, used to derive a default session name
+    pub name: String,
+    pub rule: Option<usize>,
+}
+
+/// Walk up from clog's parent, nearest ancestor first. Reads only the
+/// `/proc` entries of the ancestors themselves, never the whole process table.
+#[cfg(target_os = "linux")]
+pub fn ancestry() -> Vec<Ancestor> {
+    // Start times as sysinfo computes them, so sessions anchored by older
+    // builds still match: boot time plus whole seconds since boot
+    let boot_time = proc_boot_time();
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    let mut ancestors = Vec::new();
+
+    let Some((mut parent_pid, _, _)) = proc_stat(process::id()) else { return ancestors };
+    for depth in 1..=MAX_ANCESTRY {
+        if parent_pid == 0 {
+            break;
         }
-        
+        let Some((grandparent, name, start_ticks)) = proc_stat(parent_pid) else { break };
+        let dir = format!("/proc/{}", parent_pid);
+        let cmdline = std::fs::read(format!("{}/cmdline", dir)).unwrap_or_default();
+
+        ancestors.push(Ancestor {
+            depth,
+            pid: parent_pid,
+            start_time: boot_time + start_ticks / ticks_per_second,
+            name,
+            exe: std::fs::canonicalize(format!("{}/exe", dir)).ok().map(|p| p.to_string_lossy().to_string()),
+            cmdline: cmdline
+                .split(|&b| b == 0)
+                .map(|arg| String::from_utf8_lossy(arg).trim().to_string())
+                .filter(|arg| !arg.is_empty())
+                .collect::<Vec<_>>()
+                .join(" "),
+        });
+        parent_pid = grandparent;
+    }
+    ancestors
+}
+
+/// Parent PID, name and start time (clock ticks since boot) from
+/// `/proc/<pid>/stat`
+#[cfg(target_os = "linux")]
+fn proc_stat(pid: u32) -> Option<(u32, String, u64)> {
+    let stat = std::fs::read(format!("/proc/{}/stat", pid)).ok()?;
+    parse_proc_stat(&String::from_utf8_lossy(&stat))
+}
+
+/// The name sits in parentheses and may itself contain spaces and
+/// parentheses, so fields are counted from the last `)`
+#[cfg(target_os = "linux")]
+fn parse_proc_stat(stat: &str) -> Option<(u32, String, u64)> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat.get(open + 1..close)?.to_string();
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    // Fields 4 (ppid) and 22 (starttime) of proc(5); the list starts at field 3
+    Some((fields.get(1)?.parse().ok()?, name, fields.get(19)?.parse().ok()?))
+}
+
+#[cfg(target_os = "linux")]
+fn proc_boot_time() -> u64 {
+    std::fs::read_to_string("/proc/stat")
+        .ok()
+        .and_then(|s| s.lines().find_map(|l| l.strip_prefix("btime")?.trim().parse().ok()))
+        .unwrap_or(0)
+}
+
+/// Walk up from clog's parent, nearest ancestor first. Refreshes only the
+/// process list itself (names, parents, exe and command lines), skipping
+/// CPU, memory and disk usage.
+#[cfg(not(target_os = "linux"))]
+pub fn ancestry() -> Vec<Ancestor> {
+    use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, UpdateKind};
+
+    let mut system = System::new();
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::All,
+        ProcessRefreshKind::new().with_exe(UpdateKind::OnlyIfNotSet).with_cmd(UpdateKind::OnlyIfNotSet),
+    );
+    let mut ancestors = Vec::new();
+
+    let mut check_pid = sysinfo::Pid::from_u32(process::id());
+    for depth in 1..=MAX_ANCESTRY {
+        let Some(parent_pid) = system.process(check_pid).and_then(|p| p.parent()) else { break };
+        let Some(parent) = system.process(parent_pid) else { break };
+
+        ancestors.push(Ancestor {
+            depth,
+            pid: parent_pid.as_u32(),
+            start_time: parent.start_time(),
+            name: parent.name().to_string_lossy().to_string(),
+            exe: parent.exe().map(|p| p.to_string_lossy().to_string()),
+            cmdline: parent.cmd().iter().map(|a| a.to_string_lossy()).collect::<Vec<_>>().join(" "),
+        });
         check_pid = parent_pid;
     }
-    
-    // Second pass: fallback to login if no AI assistant found
-    check_pid = current_pid;
-    for _ in 0..20 {
-        let process = system.process(check_pid)?;
-        let parent_pid = process.parent()?;
-        let parent_process = system.process(parent_pid)?;
-        
-        let name = parent_process.name().to_string_lossy().to_lowercase();
-        
-        if name == "login" {
-            return Some(parent_pid.as_u32());
+    ancestors
+}
+
+const MAX_ANCESTRY: usize = 64;
+
+/// Nearest ancestor matching the rule, if any
+pub fn rule_match<'a>(rule: &AnchorRule, ancestors: &'a [Ancestor]) -> Option<&'a Ancestor> {
+    ancestors
+        .iter()
+        .take_while(|a| a.depth <= rule.max_depth)
+        .find(|a| {
+            rule.name.as_ref().is_none_or(|r| r.is_match(&a.name))
+                && rule.exe.as_ref().is_none_or(|r| a.exe.as_deref().is_some_and(|e| r.is_match(e)))
+                && rule.cmdline.as_ref().is_none_or(|r| r.is_match(&a.cmdline))
+        })
+}
+
+/// Apply the rules in order; the first rule with a matching ancestor wins.
+/// Falls back to the immediate parent when nothing matches.
+pub fn resolve_anchor(rules: &[AnchorRule], ancestors: &[Ancestor]) -> Option<Anchor> {
+    rules
+        .iter()
+        .enumerate()
+        .find_map(|(i, rule)| rule_match(rule, ancestors).map(|a| a.to_anchor(Some(i))))
+        .or_else(|| ancestors.first().map(|a| a.to_anchor(None)))
+}
+
+/// Find the stable parent process (Claude Code, Codex, Gemini, or shell by
+/// default) by climbing up the process tree and applying the anchor rules
+pub fn get_anchor(rules: &[AnchorRule]) -> Option<Anchor> {
+    let ancestors = ancestry();
+
+    if std::env::var("CLOG_DEBUG").is_ok() {
+        eprintln!("Current PID: {}", process::id());
+        for a in ancestors.iter().take(5) {
+            eprintln!("  [{}] PID {} - {}", a.depth, a.pid, a.name);
         }
-        
-        check_pid = parent_pid;
     }
-    
-    // Fallback to immediate parent if we can't find a target process
-    system.process(sysinfo::Pid::from_u32(pid))
-        .and_then(|process| process.parent())
-        .map(|ppid| ppid.as_u32())
-}
\ No newline at end of file
+
+    resolve_anchor(rules, &ancestors)
+}
+
+/// Anchor for clog run from a git hook. The rules apply as usual, but only
+/// above `git`, so the fallback is the process that ran git rather than the
+/// short-lived hook shell.
+pub fn get_hook_anchor(rules: &[AnchorRule]) -> Option<Anchor> {
+    let ancestors = ancestry();
+    let above_git = match ancestors.iter().position(|a| a.name == "git") {
+        Some(i) => &ancestors[i + 1..],
+        None => &ancestors[..],
+    };
+    resolve_anchor(rules, above_git)
+}
+
+/// Default name for a session nobody has named yet: `{process}@{tty}` when the
+/// anchor has a terminal on stdin, otherwise `{process}:{pid}`
+pub fn auto_name(anchor: &Anchor) -> String {
+    name_with_tty(anchor, tty_of(anchor.pid).as_deref())
+}
+
+fn name_with_tty(anchor: &Anchor, tty: Option<&str>) -> String {
+    match tty {
+        Some(tty) => format!("{}@{}", anchor.name, tty),
+        None => format!("{}:{}", anchor.name, anchor.pid),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn tty_of(pid: u32) -> Option<String> {
+    let target = std::fs::read_link(format!("/proc/{}/fd/0", pid)).ok()?;
+    tty_name(target.to_str()?)
+}
+
+/// `pts3` for `/dev/pts/3`, `tty1` for `/dev/tty1`; `None` for anything
+/// that isn't a terminal
+#[cfg(target_os = "linux")]
+fn tty_name(device: &str) -> Option<String> {
+    let dev = device.strip_prefix("/dev/")?;
+    if dev.starts_with("pts/") || dev.starts_with("tty") {
+        Some(dev.replace('/', ""))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn tty_of(_pid: u32) -> Option<String> {
+    None
+}
+
+/// Ask for a session name when running interactively. Returns `None` when
+/// not on a terminal or the user accepts the suggested default.
+pub fn prompt_for_name(default: &str) -> Option<String> {
+    use std::io::{BufRead, IsTerminal, Write};
+
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return None;
+    }
+    eprint!("New session. Name it [{}]: ", default);
+    std::io::stderr().flush().ok()?;
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line).ok()?;
+    Some(line.trim().to_string()).filter(|n| !n.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ancestor(depth: usize, name: &str, exe: &str, cmdline: &str) -> Ancestor {
+        Ancestor {
+            depth,
+            pid: 100 + depth as u32,
+            start_time: 1_700_000_000 + depth as u64,
+            name: name.to_string(),
+            exe: Some(exe.to_string()).filter(|e| !e.is_empty()),
+            cmdline: cmdline.to_string(),
+        }
+    }
+
+    fn rule(json: &str) -> AnchorRule {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn tree() -> Vec<Ancestor> {
+        vec![
+            ancestor(1, "bash", "/usr/bin/bash", "bash"),
+            ancestor(2, "node", "/usr/bin/node", "node /opt/claude/cli.js --resume"),
+            ancestor(3, "tmux: server", "", "tmux new -s work"),
+            ancestor(4, "sshd", "/usr/sbin/sshd", "sshd: alice@pts/0"),
+        ]
+    }
+
+    #[test]
+    fn rules_match_name_exe_and_cmdline() {
+        let ancestors = tree();
+        // Names match case-insensitively, exe and cmdline as written
+        assert_eq!(rule_match(&rule(r#"{"name": "^NODE$"}"#), &ancestors).map(|a| a.pid), Some(102));
+        assert_eq!(rule_match(&rule(r#"{"exe": "/sbin/sshd$"}"#), &ancestors).map(|a| a.pid), Some(104));
+        assert_eq!(rule_match(&rule(r#"{"cmdline": "claude/cli\\.js"}"#), &ancestors).map(|a| a.pid), Some(102));
+        assert!(rule_match(&rule(r#"{"cmdline": "CLAUDE"}"#), &ancestors).is_none());
+        // Every pattern of a rule has to match the same ancestor
+        assert!(rule_match(&rule(r#"{"name": "node", "exe": "sshd"}"#), &ancestors).is_none());
+        // A missing exe never matches an exe pattern
+        assert!(rule_match(&rule(r#"{"name": "tmux", "exe": "."}"#), &ancestors).is_none());
+        // Nearest ancestor first, and nothing above max_depth
+        assert_eq!(rule_match(&rule(r#"{"name": "sh"}"#), &ancestors).map(|a| a.pid), Some(101));
+        assert!(rule_match(&rule(r#"{"name": "sshd", "max_depth": 3}"#), &ancestors).is_none());
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let ancestors = tree();
+        let rules = [rule(r#"{"name": "^zsh$"}"#), rule(r#"{"name": "sshd"}"#), rule(r#"{"name": "node"}"#)];
+        let anchor = resolve_anchor(&rules, &ancestors).unwrap();
+        // Rule order decides, not how close the ancestor is
+        assert_eq!((anchor.pid, anchor.start_time, anchor.name.as_str(), anchor.rule), (104, 1_700_000_004, "sshd", Some(1)));
+
+        let fallback = resolve_anchor(&[rule(r#"{"name": "^zsh$"}"#)], &ancestors).unwrap();
+        assert_eq!((fallback.pid, fallback.rule), (101, None));
+        assert!(resolve_anchor(&rules, &[]).is_none());
+    }
+
+    #[test]
+    fn auto_names_use_tty_else_pid() {
+        let anchor = Anchor { pid: 4242, start_time: 1, name: "zsh".to_string(), rule: None };
+        assert_eq!(name_with_tty(&anchor, Some("pts3")), "zsh@pts3");
+        assert_eq!(name_with_tty(&anchor, None), "zsh:4242");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn stat_names_with_spaces_and_parentheses() {
+        let tail = "0 0 0 -1 4194560 0 0 0 0 0 0 0 0 20 0 1 0 987654 0 0";
+        let stat = format!("1234 (tmux: server (2)) S 321 {}", tail);
+        assert_eq!(parse_proc_stat(&stat), Some((321, "tmux: server (2)".to_string(), 987654)));
+        assert_eq!(parse_proc_stat(&format!("7 () S 1 {}", tail)), Some((1, String::new(), 987654)));
+        assert_eq!(parse_proc_stat("1234 (sh) S 321 0 0"), None);
+        assert_eq!(parse_proc_stat("garbage"), None);
+
+        let (ppid, name, _) = proc_stat(process::id()).unwrap();
+        assert_eq!(ppid, immediate_parent());
+        assert!(!name.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn terminals_from_stdin_device() {
+        assert_eq!(tty_name("/dev/pts/3").as_deref(), Some("pts3"));
+        assert_eq!(tty_name("/dev/tty1").as_deref(), Some("tty1"));
+        assert_eq!(tty_name("/dev/null"), None);
+        assert_eq!(tty_name("pipe:[1234]"), None);
+
+        // A process reading from /dev/null has no terminal, so it's named by pid
+        let mut child = process::Command::new("sleep").arg("5").stdin(process::Stdio::null()).spawn().unwrap();
+        let anchor = Anchor { pid: child.id(), start_time: 0, name: "sleep".to_string(), rule: None };
+        assert_eq!(auto_name(&anchor), format!("sleep:{}", child.id()));
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use dirs::home_dir;
+use sha2::{Digest, Sha256};
+
+/// Characters of the id shown in listings and accepted as the shortest prefix
+pub const SHORT_LEN: usize = 12;
+const MIN_PREFIX: usize = 4;
+
+/// `~/.clog/blobs`: patches stored by content hash, `ab/cdef...`
+fn blobs_dir() -> PathBuf {
+    home_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join(".clog")
+        .join("blobs")
+}
+
+/// Store a patch and return its id (SHA-256, hex). Storing the same patch
+/// twice is a no-op.
+pub fn store(patch: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+    store_at(&blobs_dir(), patch)
+}
+
+/// The patch whose id starts with `prefix`, with its full id
+pub fn load(prefix: &str) -> Result<(String, Vec<u8>), Box<dyn std::error::Error>> {
+    load_at(&blobs_dir(), prefix)
+}
+
+pub fn short(id: &str) -> &str {
+    &id[..SHORT_LEN.min(id.len())]
+}
+
+fn store_at(dir: &Path, patch: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+    let id: String = Sha256::digest(patch).iter().map(|b| format!("{:02x}", b)).collect();
+    let path = dir.join(&id[..2]).join(&id[2..]);
+    if !path.exists() {
+        fs::create_dir_all(path.parent().expect("blob path has a parent"))?;
+        // Write then rename, so a blob under its final name is always complete
+        let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+        fs::write(&tmp, patch)?;
+        fs::rename(&tmp, &path)?;
+    }
+    Ok(id)
+}
+
+fn load_at(dir: &Path, prefix: &str) -> Result<(String, Vec<u8>), Box<dyn std::error::Error>> {
+    let prefix = prefix.to_lowercase();
+    if prefix.len() < MIN_PREFIX || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid snapshot id: {} (need at least {} hex digits)", prefix, MIN_PREFIX).into());
+    }
+    let (fan, rest) = prefix.split_at(2);
+    let mut matches = Vec::new();
+    if let Ok(files) = fs::read_dir(dir.join(fan)) {
+        for file in files.flatten() {
+            let name = file.file_name().to_string_lossy().to_string();
+            if name.starts_with(rest) && !name.contains('.') {
+                matches.push(format!("{}{}", fan, name));
+            }
+        }
+    }
+    match matches.as_slice() {
+        [id] => Ok((id.clone(), fs::read(dir.join(fan).join(&id[2..]))?)),
+        [] => Err(format!("No snapshot {} in {} (snapshots stay on the machine that took them)", prefix, dir.display()).into()),
+        _ => Err(format!("Snapshot id {} is ambiguous; give more digits", prefix).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_by_content_and_loads_by_prefix() {
+        let dir = std::env::temp_dir().join(format!("clog-blobs-{}", ulid::Ulid::new()));
+        let id = store_at(&dir, b"diff --git a/x b/x\n").unwrap();
+        assert_eq!(id.len(), 64);
+        assert_eq!(store_at(&dir, b"diff --git a/x b/x\n").unwrap(), id);
+
+        let (full, patch) = load_at(&dir, &id[..8].to_uppercase()).unwrap();
+        assert_eq!(full, id);
+        assert_eq!(patch, b"diff --git a/x b/x\n");
+        assert!(load_at(&dir, &id[..3]).is_err());
+        let missing = if id.starts_with('0') { "1111" } else { "0000" };
+        assert!(load_at(&dir, missing).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+use std::env;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use dirs::home_dir;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use crate::models::LogEntry;
+use crate::session::{Anchor, NamedSession};
+
+const PENDING: &str = "pending.jsonl";
+const WRITER_LOCK: &str = "writer.lock";
+const WRITER_LOG: &str = "writer.log";
+
+/// An entry logged with `--async`, waiting for the background writer
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub anchor: Anchor,
+    pub named: Option<NamedSession>,
+    pub entry: LogEntry,
+    pub files: Vec<String>,
+}
+
+/// `~/.clog/spool`: clients append jobs to `pending.jsonl`; the writer
+/// renames it to `batch-<ulid>.jsonl` before working through it
+fn spool_dir() -> PathBuf {
+    home_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join(".clog")
+        .join("spool")
+}
+
+/// Held by the one process writing the spool to the database
+pub struct WriterLock {
+    _file: File,
+}
+
+/// Queue a job behind everything queued before it
+pub fn enqueue(job: &Job) -> Result<(), Box<dyn std::error::Error>> {
+    enqueue_at(&spool_dir(), job)
+}
+
+/// Spawn a detached writer unless one is already running
+pub fn start_writer() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = spool_dir();
+    if lock_writer_at(&dir)?.is_none() {
+        return Ok(());
+    }
+    let log = OpenOptions::new().create(true).append(true).open(dir.join(WRITER_LOG))?;
+    let mut command = Command::new(env::current_exe()?);
+    command.arg("spool-writer").stdin(Stdio::null()).stdout(Stdio::null()).stderr(log);
+    // Out of the terminal's process group, so Ctrl-C in the shell doesn't cut it short
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    command.spawn()?;
+    Ok(())
+}
+
+/// Become the writer; `None` while another process is
+pub fn lock_writer() -> Result<Option<WriterLock>, Box<dyn std::error::Error>> {
+    lock_writer_at(&spool_dir())
+}
+
+/// Nothing queued and nothing left over from an interrupted writer
+pub fn is_empty() -> bool {
+    is_empty_at(&spool_dir())
+}
+
+/// Hand the queued jobs to `write` a batch at a time, oldest first, and
+/// return how many were written. Stops at the first failure, keeping the
+/// failed batch for the next writer.
+pub fn drain<F>(_lock: &WriterLock, write: F) -> Result<usize, Box<dyn std::error::Error>>
+where
+    F: FnMut(&[Job]) -> Result<(), Box<dyn std::error::Error>>,
+{
+    drain_at(&spool_dir(), write)
+}
+
+fn enqueue_at(dir: &Path, job: &Job) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(dir)?;
+    let line = format!("{}\n", serde_json::to_string(job)?);
+    let path = dir.join(PENDING);
+    loop {
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.lock()?;
+        // The writer may have claimed the queue between open and lock, leaving
+        // this handle on its batch; start over on the fresh queue
+        if same_file(&file, &path) {
+            file.write_all(line.as_bytes())?;
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(unix)]
+fn same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+// Open files can't be renamed on Windows, so the queue is never claimed under us
+#[cfg(not(unix))]
+fn same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+fn lock_writer_at(dir: &Path) -> Result<Option<WriterLock>, Box<dyn std::error::Error>> {
+    fs::create_dir_all(dir)?;
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(dir.join(WRITER_LOCK))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(WriterLock { _file: file })),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+fn is_empty_at(dir: &Path) -> bool {
+    let pending = fs::metadata(dir.join(PENDING)).map(|m| m.len() == 0).unwrap_or(true);
+    pending && batches(dir).is_empty()
+}
+
+/// Claimed batches, oldest first
+fn batches(dir: &Path) -> Vec<PathBuf> {
+    let mut batches: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|files| {
+            files
+                .flatten()
+                .map(|f| f.path())
+                .filter(|p| p.file_name().is_some_and(|n| n.to_string_lossy().starts_with("batch-")))
+                .collect()
+        })
+        .unwrap_or_default();
+    batches.sort();
+    batches
+}
+
+fn drain_at<F>(dir: &Path, mut write: F) -> Result<usize, Box<dyn std::error::Error>>
+where
+    F: FnMut(&[Job]) -> Result<(), Box<dyn std::error::Error>>,
+{
+    let mut written = 0;
+    loop {
+        // Leftovers of an interrupted writer go before anything queued since
+        let batch = match batches(dir).into_iter().next() {
+            Some(batch) => batch,
+            None if is_empty_at(dir) => return Ok(written),
+            None => {
+                let batch = dir.join(format!("batch-{}.jsonl", Ulid::new()));
+                fs::rename(dir.join(PENDING), &batch)?;
+                batch
+            }
+        };
+        let mut jobs = Vec::new();
+        for line in read_batch(&batch)? {
+            match serde_json::from_str::<Job>(&line) {
+                Ok(job) => jobs.push(job),
+                Err(e) => eprintln!("Warning: Dropping unreadable spooled entry: {}", e),
+            }
+        }
+        // Jobs carry their event ids, so retrying a batch that was already
+        // committed (say the delete failed) adds nothing
+        write(&jobs)?;
+        written += jobs.len();
+        fs::remove_file(&batch)?;
+    }
+}
+
+fn read_batch(path: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let file = File::open(path)?;
+    // Waits for a client that opened the queue just before it was claimed
+    file.lock_shared()?;
+    let mut lines = Vec::new();
+    for line in BufReader::new(&file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(message: &str) -> Job {
+        Job {
+            anchor: Anchor { pid: 42, start_time: 1, name: "sh".to_string(), rule: None },
+            named: None,
+            entry: LogEntry { message: message.to_string(), ..Default::default() },
+            files: vec!["src/main.rs".to_string()],
+        }
+    }
+
+    #[test]
+    fn drains_in_order_and_keeps_failed_batches() {
+        let dir = env::temp_dir().join(format!("clog-spool-{}", Ulid::new()));
+        for message in ["one", "two", "three"] {
+            enqueue_at(&dir, &job(message)).unwrap();
+        }
+        assert!(!is_empty_at(&dir));
+
+        assert!(drain_at(&dir, |_| Err("database down".into())).is_err());
+
+        // Queued after the failure, so it goes after the kept batch
+        enqueue_at(&dir, &job("four")).unwrap();
+        let mut seen = Vec::new();
+        let written = drain_at(&dir, |jobs| {
+            seen.push(jobs.iter().map(|j| j.entry.message.clone()).collect::<Vec<_>>());
+            assert!(jobs.iter().all(|j| j.files == ["src/main.rs"]));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(written, 4);
+        assert_eq!(seen, [vec!["one", "two", "three"], vec!["four"]]);
+        assert!(is_empty_at(&dir));
+
+        assert!(lock_writer_at(&dir).unwrap().is_some());
+        let held = lock_writer_at(&dir).unwrap();
+        assert!(lock_writer_at(&dir).unwrap().is_none());
+        drop(held);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use crate::models::{Commit, LogEntry};
+
+/// One line of `clog timeline`
+pub enum Item {
+    Commit(Commit),
+    Entry(Box<LogEntry>),
+    /// The branch (`None` when detached) or commit differs from the
+    /// previous entry's; unchanged parts are `None`
+    Moved {
+        branch: Option<(Option<String>, Option<String>)>,
+        commit: Option<(String, String)>,
+    },
+}
+
+/// Interleave commits and entries oldest first, adding a `Moved` marker
+/// before each entry logged on a different branch or commit than the entry
+/// before it. `commits` are in `git log` order (newest first); a commit sorts
+/// before an entry with the same timestamp.
+pub fn build(commits: Vec<Commit>, entries: Vec<LogEntry>) -> Vec<Item> {
+    let mut items: Vec<(chrono::DateTime<chrono::Utc>, bool, Item)> = commits.into_iter().rev()
+        .map(|c| (c.author_time, false, Item::Commit(c)))
+        .chain(entries.into_iter().map(|e| (e.timestamp, true, Item::Entry(Box::new(e)))))
+        .collect();
+    items.sort_by_key(|(time, is_entry, _)| (*time, *is_entry));
+
+    let mut out = Vec::with_capacity(items.len());
+    let mut last: Option<(Option<String>, String)> = None;
+    for (_, _, item) in items {
+        // Entries from outside a repository (no commit) neither move anything
+        // nor count as the position the next entry moved from
+        if let Item::Entry(entry) = &item {
+            if let Some(to) = &entry.repo_commit {
+                if let Some((branch, commit)) = &last {
+                    let branch_change = (*branch != entry.repo_branch).then(|| (branch.clone(), entry.repo_branch.clone()));
+                    let commit_change = (commit != to).then(|| (commit.clone(), to.clone()));
+                    if branch_change.is_some() || commit_change.is_some() {
+                        out.push(Item::Moved { branch: branch_change, commit: commit_change });
+                    }
+                }
+                last = Some((entry.repo_branch.clone(), to.clone()));
+            }
+        }
+        out.push(item);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn commit(sha: &str, minute: u32) -> Commit {
+        Commit {
+            sha: sha.to_string(),
+            short: sha.to_string(),
+            subject: format!("commit {}", sha),
+            author_time: Utc.with_ymd_and_hms(2024, 5, 1, 12, minute, 0).unwrap(),
+        }
+    }
+
+    fn entry(message: &str, minute: u32, branch: Option<&str>, sha: Option<&str>) -> LogEntry {
+        LogEntry {
+            message: message.to_string(),
+            timestamp: Utc.with_ymd_and_hms(2024, 5, 1, 12, minute, 0).unwrap(),
+            repo_branch: branch.map(str::to_string),
+            repo_commit: sha.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn describe(items: &[Item]) -> Vec<String> {
+        items
+            .iter()
+            .map(|item| match item {
+                Item::Commit(c) => format!("commit {}", c.sha),
+                Item::Entry(e) => format!("entry {}", e.message),
+                Item::Moved { branch, commit } => format!("moved {:?} {:?}", branch, commit),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interleaves_and_marks_moves() {
+        let commits = vec![commit("c2", 20), commit("c1", 10)];
+        let entries = vec![
+            entry("start", 10, Some("main"), Some("c1")),
+            entry("same place", 15, Some("main"), Some("c1")),
+            entry("after c2", 25, Some("main"), Some("c2")),
+            entry("detached", 30, None, Some("c2")),
+            entry("outside", 35, None, None),
+            entry("back", 40, Some("main"), Some("c2")),
+        ];
+        assert_eq!(describe(&build(commits, entries)), [
+            // A commit goes before an entry with the same timestamp
+            "commit c1",
+            "entry start",
+            "entry same place",
+            "commit c2",
+            r#"moved None Some(("c1", "c2"))"#,
+            "entry after c2",
+            r#"moved Some((Some("main"), None)) None"#,
+            "entry detached",
+            // Entries outside the repo neither move nor count as a position
+            "entry outside",
+            r#"moved Some((None, Some("main"))) None"#,
+            "entry back",
+        ]);
+    }
+
+    #[test]
+    fn commits_alone_in_oldest_first_order() {
+        let items = build(vec![commit("c3", 30), commit("c2", 20), commit("c1", 10)], Vec::new());
+        assert_eq!(describe(&items), ["commit c1", "commit c2", "commit c3"]);
+    }
+}
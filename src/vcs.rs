@@ -0,0 +1,322 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::git;
+use crate::models::{RepoInfo, RepoScope};
+
+/// A version control system clog reads repository context from
+pub trait Vcs {
+    /// Stored on entries as `vcs`
+    fn name(&self) -> &'static str;
+    /// Full context for a new entry: root, branch or bookmark, revision and
+    /// dirty state
+    fn repo_info(&self, cwd: &Path) -> Option<RepoInfo>;
+    /// Root and identity only, for scoping listings
+    fn repo_scope(&self, cwd: &Path) -> Option<RepoScope>;
+}
+
+/// The VCS managing `cwd`, decided by the nearest directory holding `.jj`,
+/// `.hg` or `.git`. A colocated jj repository (`.jj` next to `.git`) is read
+/// through jj. Without any marker git still gets a chance (`GIT_DIR`, ...).
+pub fn detect(cwd: &Path, use_cli: bool) -> Box<dyn Vcs> {
+    let cwd = cwd.canonicalize().unwrap_or_else(|_| cwd.to_path_buf());
+    for dir in cwd.ancestors() {
+        if dir.join(".jj").is_dir() {
+            return Box::new(Jujutsu { root: dir.to_path_buf() });
+        }
+        if dir.join(".hg").is_dir() {
+            return Box::new(Mercurial { root: dir.to_path_buf() });
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    Box::new(Git { use_cli })
+}
+
+/// Repository context for a new entry and the name of the VCS it came from
+pub fn detect_repo_info(cwd: &Path, use_cli: bool) -> Option<(&'static str, RepoInfo)> {
+    let vcs = detect(cwd, use_cli);
+    vcs.repo_info(cwd).map(|info| (vcs.name(), info))
+}
+
+pub fn detect_repo_scope(cwd: &Path, use_cli: bool) -> Option<RepoScope> {
+    detect(cwd, use_cli).repo_scope(cwd)
+}
+
+pub struct Git {
+    pub use_cli: bool,
+}
+
+impl Vcs for Git {
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    fn repo_info(&self, cwd: &Path) -> Option<RepoInfo> {
+        git::detect_repo_info(cwd, self.use_cli)
+    }
+
+    fn repo_scope(&self, cwd: &Path) -> Option<RepoScope> {
+        git::detect_repo_scope(cwd, self.use_cli)
+    }
+}
+
+/// Mercurial, through `hg` with `HGPLAIN` set so user aliases and
+/// localization don't leak into the output
+pub struct Mercurial {
+    root: PathBuf,
+}
+
+impl Mercurial {
+    fn hg(&self, args: &[&str]) -> Option<String> {
+        run(Command::new("hg").env("HGPLAIN", "1").current_dir(&self.root).args(args))
+    }
+
+    /// `paths.default` from the repository's hgrc, normalized like a git
+    /// remote; else `root:<node>` of revision 0
+    fn id(&self) -> Option<String> {
+        let hgrc = fs::read_to_string(self.root.join(".hg").join("hgrc")).unwrap_or_default();
+        if let Some(url) = hgrc_default_path(&hgrc) {
+            return Some(git::normalize_remote_url(&url));
+        }
+        self.hg(&["log", "-r", "0", "-T", "{node}"])
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+            .map(|n| format!("root:{}", n))
+    }
+}
+
+impl Vcs for Mercurial {
+    fn name(&self) -> &'static str {
+        "hg"
+    }
+
+    fn repo_info(&self, _cwd: &Path) -> Option<RepoInfo> {
+        let out = self.hg(&["log", "-r", ".", "-T", "{node}\\n{branch}\\n{activebookmark}\\n{latesttag}\\n"])?;
+        let mut lines = out.lines();
+        let commit = lines.next()?.to_string();
+        let branch = lines.next().unwrap_or("").to_string();
+        let bookmark = lines.next().unwrap_or("").to_string();
+        let tag = lines.next().unwrap_or("").to_string();
+        let (files, untracked) = self.hg(&["status"]).map(|s| parse_hg_status(&s)).unzip();
+        let changed = files.as_ref().map(|f: &Vec<String>| f.len() as u32);
+        let root = self.root.to_string_lossy().to_string();
+
+        Some(RepoInfo {
+            worktree: root.clone(),
+            root,
+            superproject: None,
+            // The active bookmark is what hg users move around; fall back to the named branch
+            branch: [bookmark, branch].into_iter().find(|b| !b.is_empty()),
+            commit,
+            change: None,
+            // No staging area: every tracked change counts as unstaged
+            staged: changed.map(|_| 0),
+            unstaged: changed,
+            untracked,
+            upstream: None,
+            ahead: None,
+            behind: None,
+            describe: Some(tag).filter(|t| !t.is_empty() && t != "null"),
+            files: files.unwrap_or_default(),
+            id: self.id(),
+        })
+    }
+
+    fn repo_scope(&self, _cwd: &Path) -> Option<RepoScope> {
+        Some(RepoScope { id: self.id(), root: Some(self.root.to_string_lossy().to_string()), worktrees: Vec::new() })
+    }
+}
+
+/// Jujutsu, through `jj`. Entries record the change id of `@`, which survives
+/// the rewrites jj makes on every snapshot, and the git commit `@` sits on,
+/// which is what git's HEAD points at in a colocated repo.
+///
+/// Commands run with `--ignore-working-copy`: logging must not snapshot the
+/// working copy (that rewrites `@` and records an operation), so the changed
+/// files are those as of jj's last snapshot.
+pub struct Jujutsu {
+    root: PathBuf,
+}
+
+impl Jujutsu {
+    fn jj(&self, args: &[&str]) -> Option<String> {
+        run(Command::new("jj").current_dir(&self.root).args(["--color=never", "--ignore-working-copy"]).args(args))
+    }
+
+    /// The `origin` remote of the backing git repository, normalized; else
+    /// `root:<sha>` of the oldest commit, matching what git records for a
+    /// colocated checkout
+    fn id(&self) -> Option<String> {
+        let remotes = self.jj(&["git", "remote", "list"]).unwrap_or_default();
+        if let Some(url) = remotes.lines().find_map(|l| l.strip_prefix("origin ")) {
+            return Some(git::normalize_remote_url(url.trim()));
+        }
+        self.jj(&["log", "--no-graph", "-r", "roots(::@ ~ root())", "-T", "commit_id ++ \"\\n\""])?
+            .lines()
+            .min()
+            .map(|sha| format!("root:{}", sha))
+    }
+}
+
+impl Vcs for Jujutsu {
+    fn name(&self) -> &'static str {
+        "jj"
+    }
+
+    fn repo_info(&self, _cwd: &Path) -> Option<RepoInfo> {
+        let template = "change_id ++ \"\\n\" ++ parents.map(|c| c.commit_id()).join(\" \")";
+        let (change, commit) = parse_jj_revision(&self.jj(&["log", "--no-graph", "-r", "@", "-T", template])?)?;
+        let bookmarks = self.jj(&["log", "--no-graph", "-r", "heads(::@ & bookmarks())", "-T", "bookmarks ++ \"\\n\""]);
+        let files = self.jj(&["diff", "--summary", "-r", "@"]).map(|d| parse_jj_summary(&d));
+        let changed = files.as_ref().map(|f| f.len() as u32);
+        let root = self.root.to_string_lossy().to_string();
+
+        Some(RepoInfo {
+            worktree: root.clone(),
+            root,
+            superproject: None,
+            branch: bookmarks.as_deref().and_then(parse_jj_bookmarks),
+            commit,
+            change: Some(change),
+            // jj tracks everything automatically: changes in @ are the working-tree changes
+            staged: changed.map(|_| 0),
+            unstaged: changed,
+            untracked: changed.map(|_| 0),
+            upstream: None,
+            ahead: None,
+            behind: None,
+            describe: None,
+            files: files.unwrap_or_default(),
+            id: self.id(),
+        })
+    }
+
+    fn repo_scope(&self, _cwd: &Path) -> Option<RepoScope> {
+        Some(RepoScope { id: self.id(), root: Some(self.root.to_string_lossy().to_string()), worktrees: Vec::new() })
+    }
+}
+
+fn run(command: &mut Command) -> Option<String> {
+    command
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+}
+
+/// `(changed paths, untracked count)` from `hg status`
+fn parse_hg_status(status: &str) -> (Vec<String>, u32) {
+    let mut changed = Vec::new();
+    let mut untracked = 0;
+    for line in status.lines() {
+        match line.split_once(' ') {
+            Some(("?", _)) => untracked += 1,
+            Some(("M" | "A" | "R" | "!", path)) => changed.push(path.to_string()),
+            _ => {}
+        }
+    }
+    (changed, untracked)
+}
+
+/// `(change id, first parent's commit id)` of `@`, from a template printing
+/// the change id and then the parents' commit ids on the next line
+fn parse_jj_revision(out: &str) -> Option<(String, String)> {
+    let mut lines = out.lines();
+    let change = lines.next().map(str::trim).filter(|c| !c.is_empty())?;
+    let commit = lines.next().and_then(|parents| parents.split_whitespace().next())?;
+    Some((change.to_string(), commit.to_string()))
+}
+
+/// Paths from `jj diff --summary` (`M src/a.rs`, `R {old => new}` for renames)
+fn parse_jj_summary(summary: &str) -> Vec<String> {
+    summary
+        .lines()
+        .filter_map(|line| line.split_once(' ').map(|(_, path)| path))
+        .map(|path| match (path.find('{'), path.find(" => "), path.rfind('}')) {
+            // `src/{a.rs => b.rs}` names the new path `src/b.rs`
+            (Some(open), Some(arrow), Some(close)) if open < arrow && arrow < close => {
+                format!("{}{}{}", &path[..open], &path[arrow + 4..close], &path[close + 1..])
+            }
+            _ => path.to_string(),
+        })
+        .collect()
+}
+
+/// `default` from the `[paths]` section of an hgrc
+fn hgrc_default_path(hgrc: &str) -> Option<String> {
+    let mut in_paths = false;
+    for line in hgrc.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_paths = line == "[paths]";
+        } else if in_paths {
+            if let Some((key, value)) = line.split_once('=') {
+                if key.trim() == "default" && !value.trim().is_empty() {
+                    return Some(value.trim().to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// First bookmark of the nearest bookmarked ancestor. jj marks bookmarks that
+/// differ from their remote with `*` and conflicted ones with `??`.
+fn parse_jj_bookmarks(out: &str) -> Option<String> {
+    out.split_whitespace()
+        .map(|b| b.trim_end_matches(['*', '?']))
+        .find(|b| !b.is_empty() && !b.contains('@'))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hg_status_counts() {
+        let (changed, untracked) = parse_hg_status("M src/a.rs\nA b.rs\n? notes.txt\n! gone.rs\n");
+        assert_eq!(changed, ["src/a.rs", "b.rs", "gone.rs"]);
+        assert_eq!(untracked, 1);
+        assert_eq!(parse_hg_status(""), (vec![], 0));
+    }
+
+    #[test]
+    fn jj_revision() {
+        let out = "qpvuntsmwlqt\n2a1b3c4d5e6f 9f8e7d6c5b4a\n";
+        assert_eq!(parse_jj_revision(out), Some(("qpvuntsmwlqt".to_string(), "2a1b3c4d5e6f".to_string())));
+        assert_eq!(parse_jj_revision("\n"), None);
+    }
+
+    #[test]
+    fn jj_summary_paths() {
+        assert_eq!(parse_jj_summary("M src/a.rs\nA b.rs\nR src/{old.rs => new.rs}\n"), ["src/a.rs", "b.rs", "src/new.rs"]);
+    }
+
+    #[test]
+    fn hgrc_paths() {
+        let hgrc = "[ui]\ndefault = nope\n\n[paths]\ndefault = https://hg.example.com/app\ndefault-push = ssh://hg@example.com/app\n";
+        assert_eq!(hgrc_default_path(hgrc).as_deref(), Some("https://hg.example.com/app"));
+        assert_eq!(hgrc_default_path("[paths]\n"), None);
+    }
+
+    #[test]
+    fn jj_bookmarks() {
+        assert_eq!(parse_jj_bookmarks("main* main@origin\n").as_deref(), Some("main"));
+        assert_eq!(parse_jj_bookmarks("feature?? \n").as_deref(), Some("feature"));
+        assert_eq!(parse_jj_bookmarks("\n"), None);
+    }
+
+    #[test]
+    fn git_without_other_markers() {
+        let dir = std::env::temp_dir().join(format!("clog-vcs-{}", ulid::Ulid::new()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        assert_eq!(detect(&dir.join("sub"), false).name(), "git");
+        fs::create_dir_all(dir.join(".hg")).unwrap();
+        assert_eq!(detect(&dir.join("sub"), false).name(), "hg");
+        fs::create_dir_all(dir.join(".jj")).unwrap();
+        assert_eq!(detect(&dir.join("sub"), false).name(), "jj");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
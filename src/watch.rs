@@ -0,0 +1,159 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::git;
+
+/// Session the watcher logs under unless `--as` or `CLOG_SESSION` says otherwise
+pub const SESSION: &str = "git";
+
+/// A repository followed by `clog watch-repo`. Events come from the HEAD
+/// reflog of its worktree and the shared stash reflog; filesystem
+/// notifications only say when to look.
+pub struct WatchedRepo {
+    pub worktree: PathBuf,
+    /// The worktree's git directory, where HEAD changes (watched on its own)
+    pub git_dir: PathBuf,
+    /// Shared refs, where branch tips and the stash move (watched recursively)
+    pub refs_dir: PathBuf,
+    head_log: PathBuf,
+    stash_log: PathBuf,
+    /// Bytes of the HEAD reflog already seen
+    head_seen: u64,
+    stash_count: usize,
+}
+
+impl WatchedRepo {
+    /// Start following the repository at `path` from its current state
+    pub fn open(path: &Path) -> Result<WatchedRepo, Box<dyn std::error::Error>> {
+        let (worktree, git_dir, common_dir) = git::git_dirs(path)
+            .ok_or_else(|| format!("Not inside a git worktree: {}", path.display()))?;
+        let head_log = git_dir.join("logs").join("HEAD");
+        let stash_log = common_dir.join("logs").join("refs").join("stash");
+        Ok(WatchedRepo {
+            worktree,
+            git_dir,
+            refs_dir: common_dir.join("refs"),
+            head_seen: fs::metadata(&head_log).map(|m| m.len()).unwrap_or(0),
+            stash_count: read_lines(&stash_log).len(),
+            head_log,
+            stash_log,
+        })
+    }
+
+    /// Entry kinds and messages for whatever happened since the last call
+    pub fn poll(&mut self) -> Vec<(&'static str, String)> {
+        let mut events = Vec::new();
+
+        let content = fs::read(&self.head_log).unwrap_or_default();
+        if (content.len() as u64) < self.head_seen {
+            // Expired or deleted reflog: start over from its end
+            self.head_seen = content.len() as u64;
+        }
+        let new = &content[self.head_seen as usize..];
+        // Only complete lines; git may still be writing the last one
+        let complete = new.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        for line in String::from_utf8_lossy(&new[..complete]).lines() {
+            if let Some(event) = describe_reflog(line) {
+                events.push(event);
+            }
+        }
+        self.head_seen += complete as u64;
+
+        let stash = read_lines(&self.stash_log);
+        if stash.len() > self.stash_count {
+            // Newest stash is the last line
+            let message = stash.last().and_then(|l| l.split_once('\t')).map(|(_, m)| m).unwrap_or("");
+            events.push(("stash", format!("saved: {}", message)));
+        } else if stash.len() < self.stash_count {
+            events.push(("stash", format!("popped or dropped ({} left)", stash.len())));
+        }
+        self.stash_count = stash.len();
+
+        events
+    }
+}
+
+fn read_lines(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .map(|s| s.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Entry kind and message for one HEAD reflog line
+/// (`<old> <new> <ident> <time> <tz>\t<message>`), or None for steps not
+/// worth an entry (rebase picks, no-op checkouts and resets)
+pub fn describe_reflog(line: &str) -> Option<(&'static str, String)> {
+    let (header, message) = line.split_once('\t')?;
+    let mut fields = header.split(' ');
+    let old = fields.next()?;
+    let new = fields.next()?;
+    let (action, detail) = message.split_once(": ").unwrap_or((message, ""));
+
+    match action {
+        "commit" | "commit (initial)" | "cherry-pick" | "revert" => Some(("commit", format!("{} {}", short(new), detail))),
+        "commit (amend)" => Some(("commit", format!("{} {} (amend)", short(new), detail))),
+        "commit (merge)" => Some(("merge", format!("{} {}", short(new), detail))),
+        "checkout" => {
+            let (from, to) = detail.strip_prefix("moving from ")?.split_once(" to ")?;
+            if from == to && old == new {
+                return None;
+            }
+            let target = if is_sha(to) { format!("{} (detached)", short(to)) } else { to.to_string() };
+            Some(("checkout", format!("{} ({} → {})", target, short(old), short(new))))
+        }
+        "reset" if old != new => {
+            let to = detail.strip_prefix("moving to ").unwrap_or(detail);
+            Some(("reset", format!("moved to {}, now at {} (was {})", to, short(new), short(old))))
+        }
+        a if a.starts_with("merge ") || a.starts_with("pull") => Some(("merge", format!("{} {}", short(new), message))),
+        "rebase (finish)" | "rebase -i (finish)" => {
+            let branch = detail.strip_prefix("returning to ").unwrap_or(detail);
+            Some(("rewrite", format!("rebase finished on {}, now at {}", branch.trim_start_matches("refs/heads/"), short(new))))
+        }
+        _ => None,
+    }
+}
+
+fn short(sha: &str) -> &str {
+    &sha[..7.min(sha.len())]
+}
+
+fn is_sha(s: &str) -> bool {
+    s.len() >= 40 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "1111111111111111111111111111111111111111";
+    const B: &str = "2222222222222222222222222222222222222222";
+
+    fn line(old: &str, new: &str, message: &str) -> String {
+        format!("{} {} clog <clog@example.com> 1760000000 +0000\t{}", old, new, message)
+    }
+
+    #[test]
+    fn reflog_events() {
+        assert_eq!(describe_reflog(&line(A, B, "commit: Fix parser")), Some(("commit", "2222222 Fix parser".to_string())));
+        assert_eq!(describe_reflog(&line(A, B, "commit (amend): Fix parser")), Some(("commit", "2222222 Fix parser (amend)".to_string())));
+        assert_eq!(
+            describe_reflog(&line(A, B, "checkout: moving from main to feature")),
+            Some(("checkout", "feature (1111111 → 2222222)".to_string()))
+        );
+        assert_eq!(
+            describe_reflog(&line(A, B, &format!("checkout: moving from main to {}", B))),
+            Some(("checkout", "2222222 (detached) (1111111 → 2222222)".to_string()))
+        );
+        assert_eq!(
+            describe_reflog(&line(A, B, "reset: moving to HEAD~1")),
+            Some(("reset", "moved to HEAD~1, now at 2222222 (was 1111111)".to_string()))
+        );
+        assert_eq!(describe_reflog(&line(A, A, "reset: moving to HEAD")), None);
+        assert_eq!(describe_reflog(&line(A, B, "rebase (pick): Add tests")), None);
+        assert_eq!(
+            describe_reflog(&line(A, B, "rebase (finish): returning to refs/heads/feature")),
+            Some(("rewrite", "rebase finished on feature, now at 2222222".to_string()))
+        );
+        assert_eq!(describe_reflog(&line(A, B, "pull: Fast-forward")), Some(("merge", "2222222 pull: Fast-forward".to_string())));
+    }
+}